@@ -0,0 +1,169 @@
+/// The minimum of an Allan deviation curve sits this factor below the flicker
+/// floor it is caused by, `sqrt(2 ln 2 / pi)`.
+pub const BIAS_INSTABILITY_SCALE: f64 = 0.664;
+
+/// How far the local log-log slope may stray from a noise term's slope for
+/// the point to count towards that term's fit.
+const SLOPE_TOLERANCE: f64 = 0.15;
+
+#[derive(Clone, Copy, Debug)]
+pub struct AllanPoint {
+    /// Averaging time, seconds.
+    pub tau: f64,
+    /// Allan deviation, in the unit of the rate samples.
+    pub adev: f64,
+}
+
+/// Overlapping Allan deviation of rate samples taken every `tau0` seconds,
+/// at roughly `per_decade` log-spaced averaging times from `tau0` to a third
+/// of the recording.
+pub fn overlapping_adev(rates: &[f64], tau0: f64, per_decade: usize) -> Vec<AllanPoint> {
+    // integrate once; every cluster average is then a difference of two
+    let mut theta = Vec::with_capacity(rates.len() + 1);
+    theta.push(0.);
+    for rate in rates {
+        theta.push(theta.last().unwrap() + rate * tau0);
+    }
+    let n = theta.len();
+    if n < 4 {
+        return vec![];
+    }
+
+    let max_m = (n - 1) / 3;
+    let step = 10f64.powf(1. / per_decade.max(1) as f64);
+    let mut cluster_sizes = vec![];
+    let mut m = 1.;
+    while (m as usize) <= max_m {
+        if cluster_sizes.last() != Some(&(m as usize)) {
+            cluster_sizes.push(m as usize);
+        }
+        m *= step;
+    }
+
+    cluster_sizes
+        .into_iter()
+        .map(|m| {
+            let terms = n - 2 * m;
+            let sum = (0..terms)
+                .map(|k| {
+                    let d = theta[k + 2 * m] - 2. * theta[k + m] + theta[k];
+                    d * d
+                })
+                .sum::<f64>();
+            let tau = m as f64 * tau0;
+            AllanPoint {
+                tau,
+                adev: (sum / (2. * tau * tau * terms as f64)).sqrt(),
+            }
+        })
+        .collect()
+}
+
+/// Noise terms read off an Allan deviation curve. Any term the curve does
+/// not show clearly is `None`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoiseParams {
+    /// Angle (or velocity) random walk, the deviation at tau = 1 s of the
+    /// -1/2 slope: unit · √s, which is unit / √Hz.
+    pub random_walk: Option<f64>,
+    /// Flat bottom of the curve, in the rate unit.
+    pub bias_instability: Option<f64>,
+    /// Averaging time at which the bias instability was read, seconds.
+    pub bias_instability_tau: Option<f64>,
+    /// Rate random walk, from the +1/2 slope: unit / √s.
+    pub rate_random_walk: Option<f64>,
+}
+
+impl NoiseParams {
+    pub fn fit(curve: &[AllanPoint]) -> Self {
+        let bottom = curve
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1.adev.total_cmp(&b.1.adev));
+        // a minimum at either end means the curve never turned
+        let bottom = bottom.filter(|(i, _)| *i != 0 && *i != curve.len() - 1);
+        Self {
+            random_walk: fit_slope(curve, -0.5).map(|c| c.exp()),
+            bias_instability: bottom.map(|(_, p)| p.adev / BIAS_INSTABILITY_SCALE),
+            bias_instability_tau: bottom.map(|(_, p)| p.tau),
+            // sigma = K sqrt(tau / 3)
+            rate_random_walk: fit_slope(curve, 0.5).map(|c| c.exp() * 3f64.sqrt()),
+        }
+    }
+}
+
+/// Fits `ln sigma = slope * ln tau + c` with a fixed slope over the parts of
+/// the curve whose local slope is close to it, and returns `c`.
+fn fit_slope(curve: &[AllanPoint], slope: f64) -> Option<f64> {
+    let intercepts = curve
+        .windows(2)
+        .filter(|w| {
+            let local = (w[1].adev.ln() - w[0].adev.ln()) / (w[1].tau.ln() - w[0].tau.ln());
+            (local - slope).abs() < SLOPE_TOLERANCE
+        })
+        .flat_map(|w| w.iter().map(|p| p.adev.ln() - slope * p.tau.ln()))
+        .collect::<Vec<_>>();
+    (!intercepts.is_empty()).then(|| intercepts.iter().sum::<f64>() / intercepts.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    const TAU0: f64 = 0.01;
+
+    /// Standard normal draws, Box–Muller.
+    fn gaussian(rng: &mut StdRng) -> f64 {
+        let u: f64 = rng.gen_range(f64::EPSILON..1.);
+        let v: f64 = rng.gen();
+        (-2. * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+    }
+
+    /// Rates sampled every `TAU0` with angle random walk `n` and rate random
+    /// walk `k`.
+    fn synthetic(n: f64, k: f64, len: usize) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut bias = 0.;
+        (0..len)
+            .map(|_| {
+                bias += k * TAU0.sqrt() * gaussian(&mut rng);
+                bias + n / TAU0.sqrt() * gaussian(&mut rng)
+            })
+            .collect()
+    }
+
+    fn assert_close(found: Option<f64>, expected: f64, tolerance: f64) {
+        let found = found.expect("term not found");
+        assert!(
+            (found / expected - 1.).abs() < tolerance,
+            "{found} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn white_noise_follows_minus_half_slope() {
+        let curve = overlapping_adev(&synthetic(0.01, 0., 100_000), TAU0, 10);
+        for p in &curve[..curve.len() / 2] {
+            let expected = 0.01 / p.tau.sqrt();
+            assert!((p.adev / expected - 1.).abs() < 0.1, "{p:?}");
+        }
+        let params = NoiseParams::fit(&curve);
+        assert_close(params.random_walk, 0.01, 0.05);
+    }
+
+    #[test]
+    fn recovers_both_random_walks() {
+        let curve = overlapping_adev(&synthetic(0.01, 0.001, 200_000), TAU0, 10);
+        let params = NoiseParams::fit(&curve);
+        assert_close(params.random_walk, 0.01, 0.05);
+        assert_close(params.rate_random_walk, 0.001, 0.3);
+    }
+
+    #[test]
+    fn too_short_for_a_curve() {
+        assert!(overlapping_adev(&[1., 2.], TAU0, 10).is_empty());
+    }
+}
@@ -0,0 +1,485 @@
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
+
+//! Allan deviation of a long static recording.
+//!
+//! `allan [RECORDING] [--export FILE] [options]`
+//!
+//! Records raw device frames from the serial port of the shared
+//! configuration (`--serial`, `--baudrate` or the config file) into
+//! `RECORDING`, or
+//! analyses one recorded earlier: overlapping Allan deviation of every gyro
+//! and accelerometer axis, with the random walk, bias instability and rate
+//! random walk read off each curve. With `--export` the analysis is written
+//! as JSON and the tool exits without opening a window.
+
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use eframe::egui;
+use eframe::egui::plot::{Legend, Line, LineStyle, Plot};
+use eframe::epaint::Color32;
+use gui::allan::{overlapping_adev, AllanPoint, NoiseParams, BIAS_INSTABILITY_SCALE};
+use gui::config::{exit_with_usage, usage, Config};
+use gui::gyro::{decode_frames, encode_frame, open_until};
+use serde_json::json;
+
+const DEFAULT_RECORDING: &str = "static.bin";
+
+/// Averaging times per decade of tau.
+const PER_DECADE: usize = 20;
+
+const AXIS_COLOR: [Color32; 3] = [Color32::RED, Color32::GREEN, Color32::LIGHT_BLUE];
+const AXIS_NAME: [&str; 3] = ["x", "y", "z"];
+
+/// What only this tool takes, the recording and `--export`, split off the
+/// arguments; the rest is left for the shared configuration.
+struct Args {
+    recording: Option<String>,
+    export: Option<String>,
+    config: Vec<String>,
+}
+
+impl Args {
+    fn split(argv: &[String]) -> Self {
+        let mut args = Args {
+            recording: None,
+            export: None,
+            config: argv[..1].to_vec(),
+        };
+        let mut rest = argv[1..].iter();
+        while let Some(a) = rest.next() {
+            if a == "--export" {
+                args.export = rest.next().cloned();
+            } else if a.starts_with("--") {
+                // every configuration flag takes a value
+                args.config.push(a.clone());
+                args.config.extend(rest.next().cloned());
+            } else if args.recording.is_none() {
+                args.recording = Some(a.clone());
+            } else {
+                args.config.push(a.clone());
+            }
+        }
+        args
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Sensor {
+    Gyro,
+    Acc,
+}
+
+impl Sensor {
+    fn unit(&self) -> &'static str {
+        match self {
+            Sensor::Gyro => "deg/s",
+            Sensor::Acc => "g",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Sensor::Gyro => "gyro",
+            Sensor::Acc => "acc",
+        }
+    }
+}
+
+struct AxisResult {
+    curve: Vec<AllanPoint>,
+    params: NoiseParams,
+}
+
+struct Analysis {
+    samples: usize,
+    /// Mean sample interval, seconds.
+    tau0: f64,
+    gyro: Vec<AxisResult>,
+    acc: Vec<AxisResult>,
+}
+
+impl Analysis {
+    fn sensor(&self, sensor: Sensor) -> &[AxisResult] {
+        match sensor {
+            Sensor::Gyro => &self.gyro,
+            Sensor::Acc => &self.acc,
+        }
+    }
+
+    fn to_json(&self, recording: &Path) -> serde_json::Value {
+        let sensor = |sensor: Sensor| {
+            let unit = sensor.unit();
+            let axes = self
+                .sensor(sensor)
+                .iter()
+                .zip(AXIS_NAME)
+                .map(|(axis, name)| {
+                    let p = &axis.params;
+                    (
+                        name.to_owned(),
+                        json!({
+                            "random_walk": p.random_walk,
+                            "bias_instability": p.bias_instability,
+                            "bias_instability_tau": p.bias_instability_tau,
+                            "rate_random_walk": p.rate_random_walk,
+                        }),
+                    )
+                })
+                .collect::<serde_json::Map<_, _>>();
+            json!({
+                "units": {
+                    "random_walk": format!("{unit}*sqrt(s)"),
+                    "bias_instability": unit,
+                    "bias_instability_tau": "s",
+                    "rate_random_walk": format!("{unit}/sqrt(s)"),
+                },
+                "axes": axes,
+            })
+        };
+        json!({
+            "recording": recording.to_string_lossy(),
+            "samples": self.samples,
+            "sample_rate": 1. / self.tau0,
+            "duration": self.samples as f64 * self.tau0,
+            "gyro": sensor(Sensor::Gyro),
+            "acc": sensor(Sensor::Acc),
+        })
+    }
+}
+
+fn analyse(recording: &Path) -> Result<Analysis, String> {
+    let bytes = std::fs::read(recording).map_err(|e| format!("{}: {e}", recording.display()))?;
+    let frames = decode_frames(&bytes);
+    let intervals = frames
+        .iter()
+        .map(|f| f[12] as f64)
+        .filter(|dt| *dt > 0.)
+        .collect::<Vec<_>>();
+    if frames.len() < 100 || intervals.is_empty() {
+        return Err(format!(
+            "{}: only {} frames, record for longer",
+            recording.display(),
+            frames.len()
+        ));
+    }
+    let tau0 = intervals.iter().sum::<f64>() / intervals.len() as f64;
+
+    let axis = |channel: usize| {
+        let rates = frames.iter().map(|f| f[channel] as f64).collect::<Vec<_>>();
+        let curve = overlapping_adev(&rates, tau0, PER_DECADE);
+        AxisResult {
+            params: NoiseParams::fit(&curve),
+            curve,
+        }
+    };
+    Ok(Analysis {
+        samples: frames.len(),
+        tau0,
+        gyro: (0..3).map(axis).collect(),
+        acc: (3..6).map(axis).collect(),
+    })
+}
+
+fn export(analysis: &Analysis, recording: &Path, to: &Path) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(&analysis.to_json(recording)).unwrap();
+    std::fs::write(to, text).map_err(|e| format!("{}: {e}", to.display()))
+}
+
+fn default_export_path(recording: &Path) -> PathBuf {
+    recording.with_extension("allan.json")
+}
+
+fn main() -> Result<(), eframe::Error> {
+    let argv = std::env::args().collect::<Vec<_>>();
+    if argv.iter().any(|a| a == "--help" || a == "-h") {
+        print!("allan [RECORDING] [--export FILE] [options]\n\n{}", usage());
+        return Ok(());
+    }
+    let args = Args::split(&argv);
+    let config = Config::load(&args.config).unwrap_or_else(|e| exit_with_usage(e));
+    if let (Some(recording), Some(to)) = (&args.recording, &args.export) {
+        let recording = PathBuf::from(recording);
+        match analyse(&recording).and_then(|a| export(&a, &recording, Path::new(to))) {
+            Ok(()) => println!("wrote {to}"),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    let options = eframe::NativeOptions {
+        initial_window_size: Some(egui::vec2(900., 650.0)),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "Allan deviation",
+        options,
+        Box::new(move |_cc| Box::new(AllanApp::new(&config, args.recording))),
+    )
+}
+
+/// Writes frames from the serial port to a file on its own thread, so a
+/// minimised window does not stall the device.
+struct Recorder {
+    started: Instant,
+    frames: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Recorder {
+    fn start(port: PathBuf, baudrate: u32, to: &Path) -> std::io::Result<Self> {
+        // never over an earlier recording
+        let file = OpenOptions::new().write(true).create_new(true).open(to)?;
+        let mut file = BufWriter::new(file);
+        let frames = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (written, stopped) = (frames.clone(), stop.clone());
+        std::thread::spawn(move || {
+            // the port is let go along with the recording
+            let rx = open_until(&port, baudrate, stopped.clone());
+            while !stopped.load(Ordering::Relaxed) {
+                let sample = match rx.recv_timeout(Duration::from_millis(200)) {
+                    Ok(sample) => sample,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                if file.write_all(&encode_frame(&sample)).is_err() {
+                    break;
+                }
+                written.fetch_add(1, Ordering::Relaxed);
+            }
+            let _ = file.flush();
+        });
+        Ok(Self {
+            started: Instant::now(),
+            frames,
+            stop,
+        })
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+struct AllanApp {
+    port: PathBuf,
+    baudrate: u32,
+    recording: String,
+    export: String,
+    recorder: Option<Recorder>,
+    analysing: Option<Receiver<Result<Analysis, String>>>,
+    analysis: Option<Analysis>,
+    sensor: Sensor,
+    /// Last error or export message.
+    status: String,
+}
+
+impl AllanApp {
+    fn new(config: &Config, recording: Option<String>) -> Self {
+        let recording = recording.unwrap_or_else(|| DEFAULT_RECORDING.to_owned());
+        let mut app = Self {
+            port: config.serial.path.clone().into(),
+            baudrate: config.serial.baudrate,
+            export: default_export_path(Path::new(&recording))
+                .to_string_lossy()
+                .into_owned(),
+            recording,
+            recorder: None,
+            analysing: None,
+            analysis: None,
+            sensor: Sensor::Gyro,
+            status: String::new(),
+        };
+        if Path::new(&app.recording).exists() {
+            app.start_analysis();
+        }
+        app
+    }
+
+    fn start_analysis(&mut self) {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        let recording = PathBuf::from(&self.recording);
+        std::thread::spawn(move || {
+            let _ = tx.send(analyse(&recording));
+        });
+        self.analysing = Some(rx);
+        self.status.clear();
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Recording");
+            ui.text_edit_singleline(&mut self.recording);
+            let busy = self.recorder.is_some() || self.analysing.is_some();
+            if ui
+                .add_enabled(!busy, egui::Button::new("Analyse"))
+                .clicked()
+            {
+                self.start_analysis();
+            }
+            match &self.recorder {
+                Some(recorder) => {
+                    let frames = recorder.frames.load(Ordering::Relaxed);
+                    ui.label(format!(
+                        "recording {} frames, {:.0} s",
+                        frames,
+                        recorder.started.elapsed().as_secs_f64()
+                    ));
+                    if ui.button("Stop").clicked() {
+                        self.recorder = None;
+                        self.start_analysis();
+                    }
+                }
+                None => {
+                    let record = egui::Button::new(format!("Record from {}", self.port.display()));
+                    if ui.add_enabled(!busy, record).clicked() {
+                        let path = Path::new(&self.recording);
+                        match Recorder::start(self.port.clone(), self.baudrate, path) {
+                            Ok(recorder) => self.recorder = Some(recorder),
+                            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                                self.status =
+                                    format!("{}: exists, pick another name", path.display())
+                            }
+                            Err(e) => self.status = format!("{}: {e}", path.display()),
+                        }
+                    }
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Export");
+            ui.text_edit_singleline(&mut self.export);
+            if let Some(analysis) = &self.analysis {
+                if ui.button("Write JSON").clicked() {
+                    let recording = Path::new(&self.recording);
+                    self.status = match export(analysis, recording, Path::new(&self.export)) {
+                        Ok(()) => format!("wrote {}", self.export),
+                        Err(e) => e,
+                    };
+                }
+            }
+            if self.analysing.is_some() {
+                ui.spinner();
+                ui.label("analysing");
+            }
+            ui.label(&self.status);
+        });
+    }
+
+    fn params_table(&self, ui: &mut egui::Ui, analysis: &Analysis) {
+        let unit = self.sensor.unit();
+        let value = |v: Option<f64>| v.map_or("-".to_owned(), |v| format!("{v:.3e}"));
+        egui::Grid::new("params").striped(true).show(ui, |ui| {
+            ui.label("");
+            ui.label(format!("random walk, {unit}·√s"));
+            ui.label(format!("bias instability, {unit}"));
+            ui.label("at τ, s");
+            ui.label(format!("rate random walk, {unit}/√s"));
+            ui.end_row();
+            for (axis, name) in analysis.sensor(self.sensor).iter().zip(AXIS_NAME) {
+                let p = &axis.params;
+                ui.label(name);
+                ui.label(value(p.random_walk));
+                ui.label(value(p.bias_instability));
+                ui.label(
+                    p.bias_instability_tau
+                        .map_or("-".to_owned(), |t| format!("{t:.1}")),
+                );
+                ui.label(value(p.rate_random_walk));
+                ui.end_row();
+            }
+        });
+        ui.label(format!(
+            "{} samples at {:.1} Hz, {:.0} s",
+            analysis.samples,
+            1. / analysis.tau0,
+            analysis.samples as f64 * analysis.tau0
+        ));
+    }
+
+    fn curve_plot(&self, ui: &mut egui::Ui, analysis: &Analysis) {
+        let log = |p: &AllanPoint| [p.tau.log10(), p.adev.log10()];
+        let unit = self.sensor.unit();
+        Plot::new("adev")
+            .legend(Legend::default())
+            .x_axis_formatter(|x, _| format!("{:.3} s", 10f64.powf(x)))
+            .y_axis_formatter(move |y, _| format!("{:.2e} {unit}", 10f64.powf(y)))
+            .show(ui, |plot_ui| {
+                for (i, axis) in analysis.sensor(self.sensor).iter().enumerate() {
+                    plot_ui.line(
+                        Line::new(axis.curve.iter().map(log).collect::<Vec<_>>())
+                            .color(AXIS_COLOR[i])
+                            .name(AXIS_NAME[i]),
+                    );
+
+                    // the fitted terms, over the whole tau range
+                    let (Some(first), Some(last)) = (axis.curve.first(), axis.curve.last()) else {
+                        continue;
+                    };
+                    let taus = [first.tau, last.tau];
+                    let p = &axis.params;
+                    let fits = [
+                        p.random_walk.map(|n| taus.map(|t| [t, n / t.sqrt()])),
+                        p.bias_instability
+                            .map(|b| taus.map(|t| [t, b * BIAS_INSTABILITY_SCALE])),
+                        p.rate_random_walk
+                            .map(|k| taus.map(|t| [t, k * (t / 3.).sqrt()])),
+                    ];
+                    for fit in fits.into_iter().flatten() {
+                        let points = fit.map(|[t, s]| [t.log10(), s.log10()]);
+                        plot_ui.line(
+                            Line::new(points.to_vec())
+                                .color(AXIS_COLOR[i].linear_multiply(0.5))
+                                .style(LineStyle::Dashed { length: 6. }),
+                        );
+                    }
+                }
+            });
+    }
+}
+
+impl eframe::App for AllanApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if let Some(rx) = &self.analysing {
+            if let Ok(result) = rx.try_recv() {
+                match result {
+                    Ok(analysis) => self.analysis = Some(analysis),
+                    Err(e) => self.status = e,
+                }
+                self.analysing = None;
+            }
+        }
+
+        egui::TopBottomPanel::top("controls").show(ctx, |ui| self.controls(ui));
+        let Some(analysis) = self.analysis.take() else {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.label("Record a few hours with the device standing still, then analyse.");
+            });
+            ctx.request_repaint_after(Duration::from_millis(200));
+            return;
+        };
+        egui::TopBottomPanel::bottom("params").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                for sensor in [Sensor::Gyro, Sensor::Acc] {
+                    ui.selectable_value(&mut self.sensor, sensor, sensor.name());
+                }
+            });
+            self.params_table(ui, &analysis);
+        });
+        egui::CentralPanel::default().show(ctx, |ui| self.curve_plot(ui, &analysis));
+        self.analysis = Some(analysis);
+
+        ctx.request_repaint_after(Duration::from_millis(200));
+    }
+}
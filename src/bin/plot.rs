@@ -1,72 +1,618 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, TryRecvError};
 use eframe::egui;
-use eframe::egui::plot::Line;
+use eframe::egui::plot::{Legend, Line, LinkedAxisGroup, PlotBounds, VLine};
 use eframe::epaint::Color32;
-use gui::gyro::{open, Port};
+use gui::config::{exit_with_usage, usage, Config};
+use gui::gyro::{open_until, Port};
+use gui::series::Series;
 
 fn main() -> Result<(), eframe::Error> {
     // env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
+    let argv = std::env::args().collect::<Vec<_>>();
+    if argv.iter().any(|a| a == "--help" || a == "-h") {
+        print!("{}", usage());
+        return Ok(());
+    }
+    let config = Config::load(&argv).unwrap_or_else(|e| exit_with_usage(e));
     let options = eframe::NativeOptions {
-        initial_window_size: Some(egui::vec2(800., 600.0)),
+        initial_window_size: Some(egui::vec2(config.ui.width, config.ui.height)),
         ..Default::default()
     };
     eframe::run_native(
-        "My egui App",
+        &format!("{} plot", config.ui.title),
         options,
-        Box::new(|_cc| Box::<MyApp>::default()),
+        Box::new(|_cc| Box::new(MyApp::new(config))),
     )
 }
 
+/// Twelve floats and the timestamp, as decoded from a frame.
+const CHANNELS: usize = 13;
+
+/// Longest window the slider offers, seconds.
+const MAX_WINDOW: f64 = 600.;
+
+/// Points drawn per line; anything longer is decimated.
+const MAX_DRAWN: usize = 2000;
+
+/// How long the device may stay silent before we call it out.
+const STALE_AFTER: Duration = Duration::from_secs(1);
+
+struct Channel {
+    name: String,
+    enabled: bool,
+    color: Color32,
+    series: Series,
+}
+
+/// Channels that share a plot, a unit and a y range.
+struct Group {
+    name: &'static str,
+    channels: Range<usize>,
+    unit: String,
+    visible: bool,
+    autoscale: bool,
+    /// Fixed y range, used when not autoscaling.
+    range: (f64, f64),
+}
+
+impl Group {
+    fn new(name: &'static str, channels: Range<usize>, unit: &str, range: (f64, f64)) -> Self {
+        Self {
+            name,
+            channels,
+            unit: unit.to_owned(),
+            visible: true,
+            autoscale: true,
+            range,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TriggerMode {
+    Off,
+    /// Re-arms after every capture.
+    Normal,
+    /// Captures once and stops until re-armed.
+    Single,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TriggerKind {
+    Rising,
+    Falling,
+    Above,
+    Below,
+}
+
+struct Trigger {
+    mode: TriggerMode,
+    kind: TriggerKind,
+    channel: usize,
+    level: f64,
+    /// Seconds kept before and after the trigger point.
+    pre: f64,
+    post: f64,
+    armed: bool,
+    prev: Option<f64>,
+    /// Time of a trigger still waiting for its post-trigger samples.
+    fired_at: Option<f64>,
+}
+
+impl Trigger {
+    fn check(&mut self, value: f64) -> bool {
+        let prev = self.prev.replace(value);
+        match self.kind {
+            TriggerKind::Rising => prev.is_some_and(|p| p < self.level && value >= self.level),
+            TriggerKind::Falling => prev.is_some_and(|p| p > self.level && value <= self.level),
+            TriggerKind::Above => value > self.level,
+            TriggerKind::Below => value < self.level,
+        }
+    }
+}
+
+/// A still picture of every channel: what the plots show while paused or
+/// after a trigger.
+struct Capture {
+    series: Vec<Series>,
+    range: (f64, f64),
+    trigger: Option<f64>,
+}
+
 struct MyApp {
     port: Port,
-    prev: [f64; 12],
-    lines: Vec<Vec<[f64; 2]>>,
-    x: f64,
-    // name: String,
-    // age: u32,
+    /// Set while the port is being opened in the background; `open` retries
+    /// until the device shows up.
+    opening: Option<Receiver<Receiver<Vec<f32>>>>,
+    /// Tells the reader of the current port to give up.
+    stop: Arc<AtomicBool>,
+    disconnected: bool,
+    channels: Vec<Channel>,
+    groups: Vec<Group>,
+    x_axis: LinkedAxisGroup,
+    /// Device time of the newest sample, summed from the per-sample deltas.
+    time: f64,
+    /// Seconds of device time kept and shown.
+    window: f64,
+    paused: bool,
+    trigger: Trigger,
+    capture: Option<Capture>,
+    /// Device time under the mouse, and the two measurement cursors.
+    hover: Option<f64>,
+    cursors: [Option<f64>; 2],
+    /// Where the port comes from; edited and saved from the settings menu.
+    config: Config,
+    /// Outcome of the last save.
+    saved: String,
 }
 
-impl Default for MyApp {
-    fn default() -> Self {
-        Self {
-            lines: vec![],
-            x: 0.,
+impl MyApp {
+    fn new(config: Config) -> Self {
+        let names = [
+            "gyro x", "gyro y", "gyro z", "acc x", "acc y", "acc z", "mag x", "mag y", "mag z",
+            "extra 0", "extra 1", "extra 2", "dt",
+        ];
+        let mut app = Self {
+            channels: names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Channel {
+                    name: name.to_string(),
+                    enabled: true,
+                    color: if i == CHANNELS - 1 {
+                        Color32::YELLOW
+                    } else {
+                        AXIS_COLOR[i % 3]
+                    },
+                    series: Series::default(),
+                })
+                .collect(),
+            groups: vec![
+                Group::new("Gyro", 0..3, "deg/s", (-500., 500.)),
+                Group::new("Accelerometer", 3..6, "", (-2., 2.)),
+                Group::new("Magnetometer", 6..9, "", (-1., 1.)),
+                Group::new("Extra", 9..12, "", (-1., 1.)),
+                Group::new("Timing", 12..13, "s", (0., 0.1)),
+            ],
+            x_axis: LinkedAxisGroup::x(),
+            time: 0.,
+            window: 10.,
             port: Port {
-                rx: Some(open(std::path::Path::new("/dev/ttyUSB0"), 115200)),
+                rx: None,
                 last_transmition: None,
             },
-            prev: [0.; 12],
+            opening: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            disconnected: false,
+            paused: false,
+            trigger: Trigger {
+                mode: TriggerMode::Off,
+                kind: TriggerKind::Rising,
+                channel: 0,
+                level: 0.,
+                pre: 0.5,
+                post: 0.5,
+                armed: true,
+                prev: None,
+                fired_at: None,
+            },
+            capture: None,
+            hover: None,
+            cursors: [None; 2],
+            config,
+            saved: String::new(),
+        };
+        app.connect();
+        app
+    }
+
+    fn connect(&mut self) {
+        // the previous port, still open or still being waited for, is let go
+        self.stop.store(true, Ordering::Relaxed);
+        self.stop = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        let path = self.config.serial.path.clone();
+        let baudrate = self.config.serial.baudrate;
+        let stop = self.stop.clone();
+        std::thread::spawn(move || {
+            let _ = tx.send(open_until(std::path::Path::new(&path), baudrate, stop));
+        });
+        self.opening = Some(rx);
+        self.port.rx = None;
+        self.disconnected = false;
+    }
+
+    fn settings_menu(&mut self, ui: &mut egui::Ui) {
+        ui.menu_button("Settings", |ui| {
+            egui::Grid::new("settings").show(ui, |ui| {
+                ui.label("port");
+                ui.text_edit_singleline(&mut self.config.serial.path);
+                ui.end_row();
+                ui.label("baudrate");
+                ui.add(egui::DragValue::new(&mut self.config.serial.baudrate));
+                ui.end_row();
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Reconnect").clicked() {
+                    self.connect();
+                }
+                if ui
+                    .button(format!("Save to {}", self.config.path.display()))
+                    .clicked()
+                {
+                    self.saved = match self.config.save() {
+                        Ok(()) => "saved".to_owned(),
+                        Err(e) => e,
+                    };
+                }
+            });
+            ui.label(&self.saved);
+        });
+    }
+
+    /// Takes whatever arrived since the last repaint without waiting.
+    fn drain(&mut self) {
+        if let Some(opening) = &self.opening {
+            if let Ok(rx) = opening.try_recv() {
+                self.port.rx = Some(rx);
+                self.opening = None;
+            }
+        }
+        let Some(rx) = self.port.rx.clone() else {
+            return;
+        };
+        loop {
+            match rx.try_recv() {
+                Ok(data) => {
+                    self.time += data[12] as f64;
+                    // the trigger window has to fit in what is kept
+                    let keep = self.window.max(self.trigger.pre + self.trigger.post);
+                    for (channel, value) in self.channels.iter_mut().zip(data.iter()) {
+                        channel.series.push(self.time, *value as f64);
+                        channel.series.trim(keep);
+                    }
+                    self.port.last_transmition = Some(Instant::now());
+                    self.run_trigger(data[self.trigger.channel] as f64);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.port.rx = None;
+                    self.disconnected = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn run_trigger(&mut self, value: f64) {
+        let trigger = &mut self.trigger;
+        if trigger.mode == TriggerMode::Off || self.paused {
+            trigger.prev = None;
+            return;
+        }
+        if trigger.armed && trigger.fired_at.is_none() && trigger.check(value) {
+            trigger.fired_at = Some(self.time);
+        }
+        let Some(at) = trigger.fired_at else {
+            return;
+        };
+        if self.time < at + trigger.post {
+            return;
+        }
+
+        let range = (at - trigger.pre, at + trigger.post);
+        trigger.fired_at = None;
+        trigger.prev = None;
+        trigger.armed = trigger.mode == TriggerMode::Normal;
+        self.capture = Some(self.snapshot(range, Some(at)));
+    }
+
+    fn snapshot(&self, range: (f64, f64), trigger: Option<f64>) -> Capture {
+        Capture {
+            series: self
+                .channels
+                .iter()
+                .map(|c| c.series.slice(range.0, range.1))
+                .collect(),
+            range,
+            trigger,
+        }
+    }
+
+    /// What the plots show for channel `i`: the capture if there is one,
+    /// live data otherwise.
+    fn shown(&self, i: usize) -> &Series {
+        match &self.capture {
+            Some(capture) => &capture.series[i],
+            None => &self.channels[i].series,
+        }
+    }
+
+    /// Values of every enabled channel under the mouse and at both cursors.
+    fn readout(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let fmt = |t: Option<f64>| t.map_or("-".to_owned(), |t| format!("{t:.4} s"));
+            ui.label(format!("cursor {}", fmt(self.hover)));
+            ui.colored_label(CURSOR_COLOR[0], format!("A {}", fmt(self.cursors[0])));
+            ui.colored_label(CURSOR_COLOR[1], format!("B {}", fmt(self.cursors[1])));
+            if let [Some(a), Some(b)] = self.cursors {
+                let dt = b - a;
+                ui.label(format!("Δt {dt:.4} s"));
+                if dt != 0. {
+                    ui.label(format!("1/Δt {:.2} Hz", 1. / dt.abs()));
+                }
+            }
+            if ui.button("Clear cursors").clicked() {
+                self.cursors = [None; 2];
+            }
+        });
+        if self.hover.is_none() && self.cursors == [None; 2] {
+            return;
+        }
+        egui::Grid::new("readout_grid")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("");
+                ui.label("cursor");
+                ui.label("A");
+                ui.label("B");
+                ui.label("B − A");
+                ui.end_row();
+                let value = |v: Option<f64>| v.map_or("-".to_owned(), |v| format!("{v:.4}"));
+                for (i, channel) in self.channels.iter().enumerate() {
+                    if !channel.enabled {
+                        continue;
+                    }
+                    let series = self.shown(i);
+                    let at = |t: Option<f64>| t.and_then(|t| series.value_at(t));
+                    let (a, b) = (at(self.cursors[0]), at(self.cursors[1]));
+                    ui.colored_label(channel.color, &channel.name);
+                    ui.label(value(at(self.hover)));
+                    ui.label(value(a));
+                    ui.label(value(b));
+                    ui.label(value(a.zip(b).map(|(a, b)| b - a)));
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn shown_range(&self) -> (f64, f64) {
+        match &self.capture {
+            Some(capture) => capture.range,
+            None => (self.time - self.window, self.time),
         }
     }
 }
 
-const COLOR: [Color32; 6] = [
-    Color32::LIGHT_BLUE,
-    Color32::BLUE,
-    Color32::RED,
-    Color32::GREEN,
-    Color32::YELLOW,
-    Color32::WHITE,
-];
+const CURSOR_COLOR: [Color32; 2] = [Color32::WHITE, Color32::from_rgb(255, 140, 0)];
+
+const AXIS_COLOR: [Color32; 3] = [Color32::RED, Color32::GREEN, Color32::LIGHT_BLUE];
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        let data = self.port.rx.as_ref().unwrap().recv().unwrap();
-        for i in 0..6 {
-            self.lines
-                .push(vec![[self.x, self.prev[i]], [self.x + 1., data[i] as f64]]);
-            self.prev[i] = data[i] as f64;
-        }
-        self.x += 1.;
-        egui::CentralPanel::default().show(ctx, |ui| {
-            egui::plot::Plot::new("plot").show(ui, |plotui| {
-                // plotui.line(Line::new(vec![[0., 0.], [1., 1.]]));
-                for (line, color) in self.lines.iter().zip(COLOR.iter().cycle()) {
-                    plotui.line(Line::new(line.clone()).color(color.clone()));
+        self.drain();
+
+        egui::TopBottomPanel::top("controls").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                // no longer than the buffers hold at the device's rate
+                let longest = self.channels[0]
+                    .series
+                    .span_held()
+                    .map_or(MAX_WINDOW, |held| held.clamp(1., MAX_WINDOW));
+                self.window = self.window.min(longest);
+                ui.add(
+                    egui::Slider::new(&mut self.window, 0.1..=longest)
+                        .logarithmic(true)
+                        .text("window, s"),
+                );
+                ui.separator();
+                let pause = if self.paused { "Resume" } else { "Pause" };
+                if ui.button(pause).clicked() {
+                    self.paused = !self.paused;
+                    if !self.paused {
+                        self.capture = None;
+                    } else if self.capture.is_none() {
+                        // a trigger capture on screen is already frozen
+                        self.capture = Some(self.snapshot(self.shown_range(), None));
+                    }
+                }
+                ui.separator();
+                self.settings_menu(ui);
+                let port = self.config.serial.path.clone();
+                if self.disconnected {
+                    ui.colored_label(Color32::RED, format!("{port} disconnected"));
+                    if ui.button("Reconnect").clicked() {
+                        self.connect();
+                    }
+                } else if self.opening.is_some() {
+                    ui.colored_label(Color32::YELLOW, format!("waiting for {port}"));
+                } else {
+                    match self.port.last_transmition {
+                        Some(t) if t.elapsed() < STALE_AFTER => {
+                            ui.colored_label(Color32::GREEN, format!("{port} live"));
+                        }
+                        Some(t) => {
+                            ui.colored_label(
+                                Color32::YELLOW,
+                                format!("no data for {:.1}s", t.elapsed().as_secs_f32()),
+                            );
+                        }
+                        None => {
+                            ui.colored_label(Color32::YELLOW, "no data yet");
+                        }
+                    }
+                }
+            });
+        });
+        egui::TopBottomPanel::top("trigger").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let trigger = &mut self.trigger;
+                ui.label("Trigger");
+                let mode = trigger.mode;
+                egui::ComboBox::from_id_source("trigger_mode")
+                    .selected_text(format!("{:?}", trigger.mode))
+                    .show_ui(ui, |ui| {
+                        for m in [TriggerMode::Off, TriggerMode::Normal, TriggerMode::Single] {
+                            ui.selectable_value(&mut trigger.mode, m, format!("{m:?}"));
+                        }
+                    });
+                if trigger.mode == TriggerMode::Off {
+                    if mode != TriggerMode::Off && !self.paused {
+                        self.capture = None;
+                    }
+                    return;
+                }
+                egui::ComboBox::from_id_source("trigger_kind")
+                    .selected_text(format!("{:?}", trigger.kind))
+                    .show_ui(ui, |ui| {
+                        for k in [
+                            TriggerKind::Rising,
+                            TriggerKind::Falling,
+                            TriggerKind::Above,
+                            TriggerKind::Below,
+                        ] {
+                            ui.selectable_value(&mut trigger.kind, k, format!("{k:?}"));
+                        }
+                    });
+                egui::ComboBox::from_id_source("trigger_channel")
+                    .selected_text(&self.channels[trigger.channel].name)
+                    .show_ui(ui, |ui| {
+                        for (i, channel) in self.channels.iter().enumerate() {
+                            ui.selectable_value(&mut trigger.channel, i, &channel.name);
+                        }
+                    });
+                ui.label("level");
+                ui.add(egui::DragValue::new(&mut trigger.level).speed(0.1));
+                ui.label("pre, s");
+                ui.add(
+                    egui::DragValue::new(&mut trigger.pre)
+                        .speed(0.01)
+                        .clamp_range(0.0..=60.0),
+                );
+                ui.label("post, s");
+                ui.add(
+                    egui::DragValue::new(&mut trigger.post)
+                        .speed(0.01)
+                        .clamp_range(0.0..=60.0),
+                );
+                if trigger.fired_at.is_some() {
+                    ui.colored_label(Color32::GREEN, "capturing");
+                } else if trigger.armed {
+                    ui.colored_label(Color32::YELLOW, "armed");
+                } else {
+                    ui.colored_label(Color32::RED, "stopped");
+                    if ui.button("Arm").clicked() {
+                        trigger.armed = true;
+                    }
+                }
+            });
+        });
+        egui::TopBottomPanel::bottom("readout").show(ctx, |ui| {
+            self.readout(ui);
+        });
+        egui::SidePanel::left("channels").show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for group in self.groups.iter_mut() {
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut group.visible, "");
+                        ui.strong(group.name);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("unit");
+                        ui.add(egui::TextEdit::singleline(&mut group.unit).desired_width(60.));
+                        ui.checkbox(&mut group.autoscale, "autoscale");
+                    });
+                    if !group.autoscale {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut group.range.0).speed(0.1));
+                            ui.label("to");
+                            ui.add(egui::DragValue::new(&mut group.range.1).speed(0.1));
+                        });
+                    }
+                    for channel in self.channels[group.channels.clone()].iter_mut() {
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut channel.enabled, "");
+                            ui.colored_label(channel.color, "■");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut channel.name).desired_width(100.),
+                            );
+                        });
+                    }
+                    ui.separator();
                 }
             });
         });
-        ctx.request_repaint();
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let visible = self.groups.iter().filter(|g| g.visible).count();
+            if visible == 0 {
+                return;
+            }
+            let height = ui.available_height() / visible as f32 - ui.spacing().item_spacing.y;
+            let (t0, t1) = self.shown_range();
+            let mut hover = None;
+            let mut cursors = self.cursors;
+            for group in self.groups.iter().filter(|g| g.visible) {
+                let unit = group.unit.clone();
+                let plot = egui::plot::Plot::new(group.name)
+                    .height(height)
+                    .legend(Legend::default())
+                    .link_axis(self.x_axis.clone())
+                    .y_axis_formatter(move |y, _| {
+                        format!("{} {unit}", (y * 1000.).round() / 1000.)
+                    });
+                plot.show(ui, |plotui| {
+                    if !group.autoscale {
+                        plotui.set_plot_bounds(PlotBounds::from_min_max(
+                            [t0, group.range.0],
+                            [t1, group.range.1],
+                        ));
+                    }
+                    for i in group.channels.clone() {
+                        let channel = &self.channels[i];
+                        if channel.enabled {
+                            plotui.line(
+                                Line::new(self.shown(i).decimated(MAX_DRAWN))
+                                    .color(channel.color)
+                                    .name(&channel.name),
+                            );
+                        }
+                    }
+
+                    if let Some(at) = self.capture.as_ref().and_then(|c| c.trigger) {
+                        plotui.vline(VLine::new(at).color(Color32::GOLD).width(1.5));
+                    }
+                    for (cursor, color) in self.cursors.iter().zip(CURSOR_COLOR) {
+                        if let Some(t) = cursor {
+                            plotui.vline(VLine::new(*t).color(color));
+                        }
+                    }
+                    if let Some(t) = self.hover {
+                        plotui.vline(VLine::new(t).color(Color32::GRAY));
+                    }
+
+                    if plotui.plot_hovered() {
+                        hover = plotui.pointer_coordinate().map(|p| p.x);
+                        // left click sets cursor A, right click cursor B
+                        if plotui.plot_clicked() {
+                            cursors[0] = hover;
+                        }
+                        if plotui.plot_secondary_clicked() {
+                            cursors[1] = hover;
+                        }
+                    }
+                });
+            }
+            self.hover = hover;
+            self.cursors = cursors;
+        });
+        // samples arrive on their own schedule, keep polling for them
+        ctx.request_repaint_after(Duration::from_millis(16));
     }
 }
@@ -0,0 +1,191 @@
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use crossbeam_channel::{Sender, TrySendError};
+use serde_json::json;
+
+use crate::gyro::{encode_frame, euler_degrees, gyro_update, GyroComponent, GyroSample, Source};
+
+/// Wire format for downstream consumers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BroadcastFormat {
+    /// One JSON object per line: every sample as the device sent it, with
+    /// the filtered channels the estimators saw under `filtered`, followed by
+    /// the attitude of every drone estimated from it.
+    JsonLines,
+    /// Samples encoded as device frames, unfiltered, so they read like the
+    /// sensor's own; attitude is not included. The
+    /// frames are rebuilt from the decoded sample, so the timestamp has gone
+    /// through seconds as an `f32` and keeps about seven significant digits.
+    /// The frame has no room for a source id, so only the first source that
+    /// produces a sample is forwarded.
+    Raw,
+}
+
+impl std::str::FromStr for BroadcastFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" | "jsonl" => Ok(Self::JsonLines),
+            "raw" => Ok(Self::Raw),
+            _ => Err(format!(
+                "unknown broadcast format `{s}`, expected `json` or `raw`"
+            )),
+        }
+    }
+}
+
+/// Messages a client may fall behind by before it is dropped.
+const CLIENT_QUEUE: usize = 256;
+
+/// A connected subscriber, written to by a thread of its own.
+pub struct Client {
+    pub addr: SocketAddr,
+    tx: Sender<Arc<[u8]>>,
+}
+
+/// Re-publishes what `Port` receives to any number of TCP clients.
+#[derive(Resource)]
+pub struct Broadcast {
+    pub local_addr: SocketAddr,
+    pub format: BroadcastFormat,
+    pub clients: Arc<Mutex<Vec<Client>>>,
+    raw_source: Option<Entity>,
+}
+
+pub fn serve(addr: impl ToSocketAddrs, format: BroadcastFormat) -> std::io::Result<Broadcast> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    let clients: Arc<Mutex<Vec<Client>>> = Arc::default();
+
+    let accepted = clients.clone();
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let Ok(addr) = stream.peer_addr() else {
+                continue;
+            };
+            let _ = stream.set_nodelay(true);
+            // lets the writer notice a client that stopped reading even
+            // after it has been dropped
+            let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+            let (tx, rx) = crossbeam_channel::bounded::<Arc<[u8]>>(CLIENT_QUEUE);
+            std::thread::spawn(move || {
+                for msg in rx {
+                    if let Err(e) = stream.write_all(&msg) {
+                        info!("broadcast: {addr} left: {e}");
+                        break;
+                    }
+                }
+            });
+            info!("broadcast: {addr} subscribed");
+            accepted.lock().unwrap().push(Client { addr, tx });
+        }
+    });
+
+    Ok(Broadcast {
+        local_addr,
+        format,
+        clients,
+        raw_source: None,
+    })
+}
+
+impl Broadcast {
+    /// Queues `msg` for every client. The frame loop never waits on a
+    /// socket: a client whose queue is full is disconnected instead.
+    fn send(&self, msg: Vec<u8>) {
+        let msg: Arc<[u8]> = msg.into();
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| match client.tx.try_send(msg.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("broadcast: {} fell behind, disconnected", client.addr);
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+}
+
+pub struct BroadcastPlugin;
+
+impl Plugin for BroadcastPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            broadcast_update
+                .after(gyro_update)
+                .run_if(resource_exists::<Broadcast>()),
+        );
+    }
+}
+
+pub fn broadcast_update(
+    mut broadcast: ResMut<Broadcast>,
+    mut samples: EventReader<GyroSample>,
+    sources: Query<&Source>,
+    drones: Query<&GyroComponent>,
+) {
+    for GyroSample {
+        source,
+        data,
+        raw: v,
+        attitudes,
+    } in samples.iter()
+    {
+        let label = sources.get(*source).map_or("", |s| s.label.as_str());
+        let msg = match broadcast.format {
+            BroadcastFormat::Raw => {
+                if *broadcast.raw_source.get_or_insert(*source) != *source {
+                    continue;
+                }
+                encode_frame(v).to_vec()
+            }
+            BroadcastFormat::JsonLines => {
+                let mut out = json!({
+                    "type": "sample",
+                    "source": label,
+                    "gyro": &v[0..3],
+                    "acc": &v[3..6],
+                    "mag": &v[6..9],
+                    "extra": &v[9..12],
+                    "t": v[12],
+                    "filtered": {
+                        "gyro": &data[0..3],
+                        "acc": &data[3..6],
+                        "mag": &data[6..9],
+                        "extra": &data[9..12],
+                    },
+                })
+                .to_string();
+                out.push('\n');
+                for (drone, rotation) in attitudes {
+                    let Ok(gyro) = drones.get(*drone) else {
+                        continue;
+                    };
+                    let (roll, pitch, yaw) = euler_degrees(*rotation);
+                    out.push_str(
+                        &json!({
+                            "type": "attitude",
+                            "source": label,
+                            "variant": format!("{:?}", gyro.variant),
+                            "roll": roll,
+                            "pitch": pitch,
+                            "yaw": yaw,
+                        })
+                        .to_string(),
+                    );
+                    out.push('\n');
+                }
+                out.into_bytes()
+            }
+        };
+        broadcast.send(msg);
+    }
+}
@@ -0,0 +1,298 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Order};
+use bevy_egui::EguiContexts;
+use serde::{Deserialize, Serialize};
+
+use crate::gyro::{GyroComponent, SelectedDrone};
+
+/// Radians of orbit per pixel of mouse drag.
+const ORBIT_SPEED: f32 = 0.005;
+/// Fraction of the distance to the focus panned per pixel of drag.
+const PAN_SPEED: f32 = 0.0015;
+/// Zoom factor per wheel line.
+const ZOOM_STEP: f32 = 0.9;
+/// Radians per second of keyboard orbit.
+const KEY_ORBIT_SPEED: f32 = 1.5;
+/// Pitch stays short of straight up or down so `looking_at` has an up.
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+const CHASE_PITCH: f32 = 0.3;
+const CHASE_DISTANCE: f32 = 10.;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CameraMode {
+    #[default]
+    Free,
+    /// The focus stays on the selected drone.
+    Follow,
+    /// Follows the selected drone and turns with its heading.
+    Chase,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Preset {
+    Front,
+    Side,
+    Top,
+    Chase,
+}
+
+/// A camera that orbits `focus` at `distance`. Yaw 0 and pitch 0 look along
+/// +Z, which is how the scene has always been viewed.
+#[derive(Component)]
+pub struct OrbitCamera {
+    pub focus: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+    pub mode: CameraMode,
+    /// Focus and distance that fit every drone; what reset goes back to.
+    pub home: (Vec3, f32),
+}
+
+impl Default for OrbitCamera {
+    fn default() -> Self {
+        Self {
+            focus: Vec3::ZERO,
+            yaw: 0.,
+            pitch: 0.,
+            distance: 15.,
+            mode: CameraMode::Free,
+            home: (Vec3::ZERO, 15.),
+        }
+    }
+}
+
+impl OrbitCamera {
+    pub fn reset(&mut self) {
+        (self.focus, self.distance) = self.home;
+        self.yaw = 0.;
+        self.pitch = 0.;
+        self.mode = CameraMode::Free;
+    }
+
+    pub fn preset(&mut self, preset: Preset) {
+        let (yaw, pitch) = match preset {
+            Preset::Front => (0., 0.),
+            Preset::Side => (FRAC_PI_2, 0.),
+            Preset::Top => (0., MAX_PITCH),
+            Preset::Chase => {
+                self.mode = CameraMode::Chase;
+                self.distance = CHASE_DISTANCE;
+                (self.yaw, CHASE_PITCH)
+            }
+        };
+        if self.mode == CameraMode::Chase && preset != Preset::Chase {
+            self.mode = CameraMode::Follow;
+        }
+        self.yaw = yaw;
+        self.pitch = pitch;
+    }
+
+    pub fn transform(&self) -> Transform {
+        let rotation = Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, 0.);
+        let eye = self.focus + rotation * Vec3::NEG_Z * self.distance;
+        Transform::from_translation(eye).looking_at(self.focus, Vec3::Y)
+    }
+}
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (camera_fit, camera_input, camera_follow, camera_apply).chain(),
+        )
+        .add_systems(Update, camera_ui.before(camera_input));
+    }
+}
+
+/// Moves home around every drone whenever new ones show up, and goes there.
+pub fn camera_fit(
+    mut camera: Query<(&mut OrbitCamera, &Projection)>,
+    drones: Query<&Transform, With<GyroComponent>>,
+    added: Query<(), Added<GyroComponent>>,
+) {
+    if added.is_empty() {
+        return;
+    }
+    let Ok((mut orbit, projection)) = camera.get_single_mut() else {
+        return;
+    };
+    let (min, max) = drones.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), t| (min.min(t.translation), max.max(t.translation)),
+    );
+    if min.x > max.x {
+        return;
+    }
+    let fov = match projection {
+        Projection::Perspective(p) => p.fov,
+        Projection::Orthographic(_) => return,
+    };
+    // leave room for the models themselves around their origins
+    let center = (min + max) / 2.;
+    let half_extent = ((max - min) / 2. + Vec3::splat(4.)).max_element();
+    orbit.home = (center, (half_extent / (fov / 2.).tan()).max(15.));
+    orbit.reset();
+}
+
+/// Whether the pointer is over the 3D view rather than some egui widget.
+/// Has to run after every panel of the frame has been laid out.
+pub fn pointer_in_view(ctx: &egui::Context) -> bool {
+    let Some(pos) = ctx.input(|i| i.pointer.hover_pos()) else {
+        return false;
+    };
+    let over_window = ctx
+        .layer_id_at(pos)
+        .is_some_and(|layer| layer.order != Order::Background);
+    !ctx.is_using_pointer() && !over_window && ctx.available_rect().contains(pos)
+}
+
+pub fn camera_input(
+    mut contexts: EguiContexts,
+    time: Res<Time>,
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mut motion: EventReader<MouseMotion>,
+    mut wheel: EventReader<MouseWheel>,
+    mut camera: Query<&mut OrbitCamera>,
+) {
+    let ctx = contexts.ctx_mut();
+    let in_view = pointer_in_view(ctx);
+    let typing = ctx.wants_keyboard_input();
+    let Ok(mut orbit) = camera.get_single_mut() else {
+        return;
+    };
+
+    let drag = motion.iter().map(|m| m.delta).sum::<Vec2>();
+    let scroll = wheel
+        .iter()
+        .map(|w| match w.unit {
+            MouseScrollUnit::Line => w.y,
+            MouseScrollUnit::Pixel => w.y / 40.,
+        })
+        .sum::<f32>();
+
+    let mut turn = Vec2::ZERO;
+    let mut zoom = 0.;
+    if in_view {
+        if buttons.pressed(MouseButton::Left) {
+            turn += drag * ORBIT_SPEED;
+        }
+        if buttons.any_pressed([MouseButton::Right, MouseButton::Middle]) && drag != Vec2::ZERO {
+            let rotation = Quat::from_euler(EulerRot::YXZ, orbit.yaw, orbit.pitch, 0.);
+            let right = rotation * Vec3::X;
+            let up = rotation * Vec3::Y;
+            let scale = orbit.distance * PAN_SPEED;
+            orbit.focus += (right * drag.x + up * drag.y) * scale;
+            orbit.mode = CameraMode::Free;
+        }
+        zoom += scroll;
+    }
+
+    if !typing {
+        let step = KEY_ORBIT_SPEED * time.delta_seconds();
+        let axis = |neg: KeyCode, pos: KeyCode| {
+            keys.pressed(pos) as i32 as f32 - keys.pressed(neg) as i32 as f32
+        };
+        turn += Vec2::new(
+            axis(KeyCode::Left, KeyCode::Right),
+            axis(KeyCode::Down, KeyCode::Up),
+        ) * step;
+        zoom += axis(KeyCode::Minus, KeyCode::Equals) * 10. * time.delta_seconds();
+
+        for (key, preset) in [
+            (KeyCode::Key1, Preset::Front),
+            (KeyCode::Key2, Preset::Side),
+            (KeyCode::Key3, Preset::Top),
+            (KeyCode::Key4, Preset::Chase),
+        ] {
+            if keys.just_pressed(key) {
+                orbit.preset(preset);
+            }
+        }
+        if keys.just_pressed(KeyCode::F) {
+            orbit.mode = match orbit.mode {
+                CameraMode::Free => CameraMode::Follow,
+                _ => CameraMode::Free,
+            };
+        }
+        if keys.just_pressed(KeyCode::R) {
+            orbit.reset();
+        }
+    }
+
+    if turn != Vec2::ZERO {
+        orbit.yaw -= turn.x;
+        orbit.pitch = (orbit.pitch + turn.y).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+    if zoom != 0. {
+        orbit.distance = (orbit.distance * ZOOM_STEP.powf(zoom)).clamp(1., 500.);
+    }
+}
+
+pub fn camera_follow(
+    selected: Res<SelectedDrone>,
+    drones: Query<(Entity, &Transform), With<GyroComponent>>,
+    mut camera: Query<&mut OrbitCamera>,
+) {
+    let Ok(mut orbit) = camera.get_single_mut() else {
+        return;
+    };
+    if orbit.mode == CameraMode::Free {
+        return;
+    }
+    let shown = selected.get(drones.iter().map(|(e, _)| e));
+    let Some((_, drone)) = shown.and_then(|e| drones.get(e).ok()) else {
+        return;
+    };
+    orbit.focus = drone.translation;
+    if orbit.mode == CameraMode::Chase {
+        let (yaw, _, _) = drone.rotation.to_euler(EulerRot::YXZ);
+        orbit.yaw = yaw;
+    }
+}
+
+pub fn camera_apply(mut camera: Query<(&mut Transform, &OrbitCamera), Changed<OrbitCamera>>) {
+    for (mut transform, orbit) in camera.iter_mut() {
+        *transform = orbit.transform();
+    }
+}
+
+pub fn camera_ui(mut contexts: EguiContexts, mut camera: Query<&mut OrbitCamera>) {
+    let Ok(mut orbit) = camera.get_single_mut() else {
+        return;
+    };
+    egui::Window::new("Camera")
+        .default_pos([20., 200.])
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                for preset in [Preset::Front, Preset::Side, Preset::Top, Preset::Chase] {
+                    if ui.button(format!("{preset:?}")).clicked() {
+                        orbit.preset(preset);
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                let mut follow = orbit.mode != CameraMode::Free;
+                if ui.checkbox(&mut follow, "follow selected").changed() {
+                    orbit.mode = if follow {
+                        CameraMode::Follow
+                    } else {
+                        CameraMode::Free
+                    };
+                }
+                if ui.button("Reset").clicked() {
+                    orbit.reset();
+                }
+            });
+            ui.label("drag: orbit, right drag: pan, wheel: zoom");
+            ui.label("arrows, -/=: orbit and zoom; 1-4: views; F: follow; R: reset");
+        });
+}
@@ -0,0 +1,436 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_egui::EguiContexts;
+use serde::{Deserialize, Serialize};
+
+use crate::gyro::Source;
+
+/// Read when neither `--config` nor `GYRO_CONFIG` names another file.
+pub const DEFAULT_PATH: &str = "gyro.toml";
+
+const INIT_ACC_WEIGHT: f32 = 0.08;
+
+/// Everything the apps used to have compiled in. Built up in layers:
+/// defaults, then the TOML file, then `GYRO_*` environment variables, then
+/// command line flags.
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// `[label=]<spec>`, one row of drones each.
+    pub sources: Vec<String>,
+    /// Shorthand for a `listen:<addr>` source.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen: Option<String>,
+    pub serial: SerialConfig,
+    pub estimator: EstimatorConfig,
+    pub ui: UiConfig,
+    pub broadcast: BroadcastConfig,
+    pub log: LogConfig,
+    /// The file this came from and is saved to.
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+/// The port the plotter reads.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SerialConfig {
+    pub path: String,
+    pub baudrate: u32,
+}
+
+/// Defaults for drones the layout says nothing about.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EstimatorConfig {
+    pub acc_weight: f32,
+    /// Samples averaged into the gyro bias before an estimator starts.
+    pub calibration_samples: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    pub title: String,
+    pub width: f32,
+    pub height: f32,
+    /// Drone layout, see `Layout`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<PathBuf>,
+    /// Where panels, camera and parameters are kept between runs, see
+    /// `Workspaces`.
+    pub session: PathBuf,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BroadcastConfig {
+    /// Re-publishes the stream on this address when set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<String>,
+    /// `json` or `raw`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// `error`, `warn`, `info`, `debug` or `trace`.
+    pub level: String,
+    /// Per-module directives in `RUST_LOG` syntax, e.g. `gui::gyro=debug`.
+    pub filter: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            sources: vec!["tcp:99.22.0.1:9922".to_owned()],
+            // sources: vec!["serial:/dev/ttyUSB0@115200".to_owned()],
+            listen: None,
+            serial: SerialConfig::default(),
+            estimator: EstimatorConfig::default(),
+            ui: UiConfig::default(),
+            broadcast: BroadcastConfig::default(),
+            log: LogConfig::default(),
+            path: DEFAULT_PATH.into(),
+        }
+    }
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            path: "/dev/ttyUSB0".to_owned(),
+            baudrate: 115200,
+        }
+    }
+}
+
+impl Default for EstimatorConfig {
+    fn default() -> Self {
+        Self {
+            acc_weight: INIT_ACC_WEIGHT,
+            calibration_samples: 100,
+        }
+    }
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            title: "Gyro".to_owned(),
+            width: 800.,
+            height: 600.,
+            layout: None,
+            session: "session.json".into(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_owned(),
+            filter: "wgpu=error,naga=warn".to_owned(),
+        }
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{value}`"))
+}
+
+type Setter = fn(&mut Config, &str) -> Result<(), String>;
+
+/// Settings that can be overridden one by one: environment variable, flag
+/// and how the value is applied.
+const OVERRIDES: &[(&str, &str, Setter)] = &[
+    ("GYRO_LISTEN", "--listen", |c, v| {
+        c.listen = Some(v.to_owned());
+        Ok(())
+    }),
+    ("GYRO_SERIAL", "--serial", |c, v| {
+        c.serial.path = v.to_owned();
+        Ok(())
+    }),
+    ("GYRO_BAUDRATE", "--baudrate", |c, v| {
+        c.serial.baudrate = parse(v)?;
+        Ok(())
+    }),
+    ("GYRO_ACC_WEIGHT", "--acc-weight", |c, v| {
+        c.estimator.acc_weight = parse(v)?;
+        Ok(())
+    }),
+    (
+        "GYRO_CALIBRATION_SAMPLES",
+        "--calibration-samples",
+        |c, v| {
+            c.estimator.calibration_samples = parse(v)?;
+            Ok(())
+        },
+    ),
+    ("GYRO_TITLE", "--title", |c, v| {
+        c.ui.title = v.to_owned();
+        Ok(())
+    }),
+    ("GYRO_LAYOUT", "--layout", |c, v| {
+        c.ui.layout = Some(v.into());
+        Ok(())
+    }),
+    ("GYRO_BROADCAST", "--broadcast", |c, v| {
+        c.broadcast.addr = Some(v.to_owned());
+        Ok(())
+    }),
+    ("GYRO_BROADCAST_FORMAT", "--broadcast-format", |c, v| {
+        c.broadcast.format = Some(v.to_owned());
+        Ok(())
+    }),
+    ("GYRO_LOG", "--log", |c, v| {
+        c.log.level = v.to_owned();
+        Ok(())
+    }),
+    ("GYRO_LOG_FILTER", "--log-filter", |c, v| {
+        c.log.filter = v.to_owned();
+        Ok(())
+    }),
+];
+
+/// The value following the last `name` in `args`.
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.windows(2)
+        .rev()
+        .find(|w| w[0] == name)
+        .map(|w| w[1].as_str())
+}
+
+/// Rejects anything in `args` past the program name that is not a known
+/// flag followed by its value, so a typo does not silently fall back to the
+/// defaults.
+fn check_flags(args: &[String]) -> Result<(), String> {
+    let known = |a: &str| {
+        a == "--config" || a == "--source" || OVERRIDES.iter().any(|(_, name, _)| *name == a)
+    };
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        if !arg.starts_with("--") {
+            return Err(format!("unexpected argument `{arg}`"));
+        }
+        if !known(arg) {
+            return Err(format!("unknown option `{arg}`"));
+        }
+        if rest.next().is_none() {
+            return Err(format!("`{arg}` needs a value"));
+        }
+    }
+    Ok(())
+}
+
+pub fn usage() -> String {
+    let mut text = format!(
+        "options, each also settable in the config file:\n  \
+         --config <file>  (GYRO_CONFIG, default {DEFAULT_PATH})\n  \
+         --source [label=]<spec>  repeatable (GYRO_SOURCES, comma separated)\n"
+    );
+    for (env, flag, _) in OVERRIDES {
+        text += &format!("  {flag} <value>  ({env})\n");
+    }
+    text
+}
+
+/// Reports a startup error followed by the usage and exits, for the binaries
+/// to call when their configuration does not add up.
+pub fn exit_with_usage(error: impl std::fmt::Display) -> ! {
+    eprint!("error: {error}\n\n{}", usage());
+    std::process::exit(2)
+}
+
+impl Config {
+    /// Defaults, overlaid by the config file, the environment and `args`,
+    /// in that order.
+    pub fn load(args: &[String]) -> Result<Self, String> {
+        check_flags(args)?;
+        let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let explicit = flag(args, "--config")
+            .map(PathBuf::from)
+            .or_else(|| env("GYRO_CONFIG").map(PathBuf::from));
+        let path = explicit.clone().unwrap_or_else(|| DEFAULT_PATH.into());
+
+        let mut config = if explicit.is_some() || path.exists() {
+            Self::read(&path)?
+        } else {
+            Self::default()
+        };
+        config.path = path;
+
+        if let Some(sources) = env("GYRO_SOURCES") {
+            config.sources = sources.split(',').map(|s| s.trim().to_owned()).collect();
+        }
+        for (name, _, set) in OVERRIDES {
+            if let Some(value) = env(name) {
+                set(&mut config, &value).map_err(|e| format!("{name}: {e}"))?;
+            }
+        }
+
+        let sources = args
+            .windows(2)
+            .filter(|w| w[0] == "--source")
+            .map(|w| w[1].clone())
+            .collect::<Vec<_>>();
+        if !sources.is_empty() {
+            config.sources = sources;
+        }
+        for (_, name, set) in OVERRIDES {
+            if let Some(value) = flag(args, name) {
+                set(&mut config, value).map_err(|e| format!("{name}: {e}"))?;
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let text = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(&self.path, text).map_err(|e| format!("{}: {e}", self.path.display()))
+    }
+
+    /// Every source to open, the `listen` shorthand included.
+    pub fn source_specs(&self) -> Vec<String> {
+        let mut specs = self.sources.clone();
+        if let Some(addr) = &self.listen {
+            specs.push(format!("listen:{addr}"));
+        }
+        specs
+    }
+}
+
+pub struct ConfigPlugin;
+
+impl Plugin for ConfigPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Config>().add_systems(Update, config_ui);
+    }
+}
+
+/// Edits the settings that matter at the next start and saves them along
+/// with the sources that are open now.
+pub fn config_ui(
+    mut contexts: EguiContexts,
+    mut config: ResMut<Config>,
+    mut status: Local<String>,
+    sources: Query<&Source>,
+) {
+    egui::Window::new("Settings")
+        .default_pos([20., 440.])
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Grid::new("config").show(ui, |ui| {
+                ui.label("acc weight");
+                ui.add(egui::Slider::new(
+                    &mut config.estimator.acc_weight,
+                    0.0..=1.0,
+                ));
+                ui.end_row();
+                ui.label("calibration");
+                ui.add(
+                    egui::DragValue::new(&mut config.estimator.calibration_samples)
+                        .clamp_range(1..=10000)
+                        .suffix(" samples"),
+                );
+                ui.end_row();
+                ui.label("window title");
+                ui.text_edit_singleline(&mut config.ui.title);
+                ui.end_row();
+                ui.label("log level");
+                egui::ComboBox::from_id_source("config_log")
+                    .selected_text(&config.log.level)
+                    .show_ui(ui, |ui| {
+                        for level in ["error", "warn", "info", "debug", "trace"] {
+                            ui.selectable_value(&mut config.log.level, level.to_owned(), level);
+                        }
+                    });
+                ui.end_row();
+                ui.label("log filter");
+                ui.text_edit_singleline(&mut config.log.filter);
+                ui.end_row();
+            });
+            ui.label("applies to new drones and the next start");
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui
+                    .button(format!("Save to {}", config.path.display()))
+                    .clicked()
+                {
+                    config.sources = sources
+                        .iter()
+                        .map(|s| {
+                            let spec = s.spec.to_string();
+                            if s.label == spec {
+                                spec
+                            } else {
+                                format!("{}={spec}", s.label)
+                            }
+                        })
+                        .collect();
+                    // now among the sources
+                    config.listen = None;
+                    *status = match config.save() {
+                        Ok(()) => "saved".to_owned(),
+                        Err(e) => e,
+                    };
+                }
+                ui.label(&*status);
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn known_flags_with_values_pass() {
+        let argv = args(&["gui", "--source", "tcp:1:2", "--baudrate", "9600"]);
+        assert_eq!(check_flags(&argv), Ok(()));
+    }
+
+    #[test]
+    fn unknown_flag_is_rejected() {
+        let argv = args(&["gui", "--sourc", "tcp:1:2"]);
+        assert_eq!(
+            check_flags(&argv),
+            Err("unknown option `--sourc`".to_owned())
+        );
+    }
+
+    #[test]
+    fn trailing_flag_without_value_is_rejected() {
+        let argv = args(&["gui", "--serial", "/dev/ttyUSB0", "--source"]);
+        assert_eq!(
+            check_flags(&argv),
+            Err("`--source` needs a value".to_owned())
+        );
+    }
+
+    #[test]
+    fn stray_argument_is_rejected() {
+        let argv = args(&["gui", "recording.bin"]);
+        assert_eq!(
+            check_flags(&argv),
+            Err("unexpected argument `recording.bin`".to_owned())
+        );
+    }
+}
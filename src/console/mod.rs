@@ -0,0 +1,281 @@
+use std::collections::VecDeque;
+use std::fmt::{Debug, Write};
+use std::time::Instant;
+
+use bevy::log::Level;
+use bevy::prelude::*;
+use bevy::utils::tracing::field::{Field, Visit};
+use bevy::utils::tracing::{self, Event, Subscriber};
+use bevy_egui::egui::{self, Color32, RichText};
+use bevy_egui::EguiContexts;
+use crossbeam_channel::{Receiver, Sender};
+use tracing_log::{LogTracer, NormalizeEvent};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+use crate::camera::camera_input;
+use crate::config::LogConfig;
+
+/// Lines kept for the console, oldest dropped first.
+const MAX_LINES: usize = 5000;
+const LEVELS: [Level; 5] = [
+    Level::ERROR,
+    Level::WARN,
+    Level::INFO,
+    Level::DEBUG,
+    Level::TRACE,
+];
+
+/// One event as the console shows it.
+pub struct LogLine {
+    /// Seconds since logging started.
+    pub time: f32,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// Takes the place of bevy's `LogPlugin`: logs to stderr as it does and
+/// keeps the recent lines for the console. Installed before anything else
+/// runs, so the sources opened at startup are heard from as well.
+#[derive(Resource)]
+pub struct LogConsole {
+    pub lines: VecDeque<LogLine>,
+    rx: Receiver<LogLine>,
+    /// The `RUST_LOG` style directives in effect.
+    pub directives: String,
+    filter: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogConsole {
+    /// `RUST_LOG`, when set, wins over `log` as it does with `LogPlugin`.
+    pub fn install(log: &LogConfig) -> Result<Self, String> {
+        let level = log
+            .level
+            .parse::<Level>()
+            .map_err(|_| format!("unknown log level `{}`", log.level))?;
+        let directives = std::env::var("RUST_LOG")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| format!("{level},{}", log.filter));
+        let filter = EnvFilter::try_new(&directives).map_err(|e| format!("{directives}: {e}"))?;
+        let (filter, handle) = reload::Layer::new(filter);
+
+        // the console only catches up once per frame, whatever arrives past
+        // a full buffer meanwhile is dropped
+        let (tx, rx) = crossbeam_channel::bounded(MAX_LINES);
+        let capture = Capture {
+            tx,
+            start: Instant::now(),
+        };
+        let subscriber = Registry::default()
+            .with(filter)
+            .with(tracing_subscriber::fmt::Layer::default().with_writer(std::io::stderr))
+            .with(capture);
+        // `log` records from the dependencies end up here too
+        let _ = LogTracer::init();
+        tracing::subscriber::set_global_default(subscriber).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            lines: VecDeque::new(),
+            rx,
+            directives,
+            filter: handle,
+        })
+    }
+
+    /// Replaces the directives deciding what is logged at all.
+    pub fn set_directives(&mut self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        self.filter.reload(filter).map_err(|e| e.to_string())?;
+        self.directives = directives.to_owned();
+        Ok(())
+    }
+
+    /// Takes in what was logged since the last call.
+    pub fn receive(&mut self) {
+        while let Ok(line) = self.rx.try_recv() {
+            if self.lines.len() >= MAX_LINES {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(line);
+        }
+    }
+}
+
+struct Capture {
+    tx: Sender<LogLine>,
+    start: Instant,
+}
+
+/// An event's message followed by its other fields as `name=value`.
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        match field.name() {
+            "message" => {
+                let _ = write!(self.message, "{value:?}");
+            }
+            // where a `log` record came from, already in the metadata
+            name if name.starts_with("log.") => {}
+            name => {
+                let _ = write!(self.rest, " {name}={value:?}");
+            }
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for Capture {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let line = LogLine {
+            time: self.start.elapsed().as_secs_f32(),
+            level: *metadata.level(),
+            target: metadata.target().to_owned(),
+            message: fields.message + &fields.rest,
+        };
+        let _ = self.tx.try_send(line);
+    }
+}
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, console_ui.before(camera_input));
+    }
+}
+
+/// What the console window shows of the captured lines.
+pub struct ConsoleView {
+    /// The least severe level shown.
+    pub level: Level,
+    /// Only lines whose target or message contain this.
+    pub search: String,
+    pub directives: Option<String>,
+    pub status: String,
+}
+
+impl Default for ConsoleView {
+    fn default() -> Self {
+        Self {
+            level: Level::TRACE,
+            search: String::new(),
+            directives: None,
+            status: String::new(),
+        }
+    }
+}
+
+fn level_color(level: Level) -> Color32 {
+    match level {
+        Level::ERROR => Color32::RED,
+        Level::WARN => Color32::YELLOW,
+        Level::INFO => Color32::LIGHT_GREEN,
+        Level::DEBUG => Color32::LIGHT_BLUE,
+        Level::TRACE => Color32::GRAY,
+    }
+}
+
+pub fn console_ui(
+    mut contexts: EguiContexts,
+    mut console: ResMut<LogConsole>,
+    mut view: Local<ConsoleView>,
+) {
+    console.receive();
+    let errors = console
+        .lines
+        .iter()
+        .filter(|l| l.level == Level::ERROR)
+        .count();
+    let title = match errors {
+        0 => "Console".to_owned(),
+        1 => "Console (1 error)".to_owned(),
+        n => format!("Console ({n} errors)"),
+    };
+    egui::Window::new(title)
+        .id(egui::Id::new("console"))
+        .default_pos([20., 480.])
+        .default_size([640., 240.])
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("console_level")
+                    .selected_text(view.level.as_str())
+                    .show_ui(ui, |ui| {
+                        for level in LEVELS {
+                            ui.selectable_value(&mut view.level, level, level.as_str());
+                        }
+                    });
+                ui.label("and above, containing");
+                ui.text_edit_singleline(&mut view.search);
+                if ui.button("Clear").clicked() {
+                    console.lines.clear();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("logged");
+                let view = &mut *view;
+                let edit = view
+                    .directives
+                    .get_or_insert_with(|| console.directives.clone());
+                let response = ui
+                    .text_edit_singleline(edit)
+                    .on_hover_text("per-module levels, e.g. info,gui::gyro=trace");
+                let changed = *edit != console.directives;
+                let enter = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if ui
+                    .add_enabled(changed, egui::Button::new("Apply"))
+                    .clicked()
+                    || (enter && changed)
+                {
+                    view.status = match console.set_directives(edit) {
+                        Ok(()) => String::new(),
+                        Err(e) => e,
+                    };
+                }
+                ui.colored_label(Color32::RED, &view.status);
+            });
+            ui.separator();
+
+            let search = view.search.to_lowercase();
+            let shown = console
+                .lines
+                .iter()
+                .filter(|l| l.level <= view.level)
+                .filter(|l| {
+                    search.is_empty()
+                        || l.target.to_lowercase().contains(&search)
+                        || l.message.to_lowercase().contains(&search)
+                })
+                .collect::<Vec<_>>();
+            let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .stick_to_bottom(true)
+                .show_rows(ui, row_height, shown.len(), |ui, rows| {
+                    for line in &shown[rows] {
+                        ui.horizontal(|ui| {
+                            ui.monospace(format!("{:8.3}", line.time));
+                            ui.label(
+                                RichText::new(format!("{:5}", line.level.as_str()))
+                                    .monospace()
+                                    .color(level_color(line.level)),
+                            );
+                            ui.label(RichText::new(&line.target).monospace().weak());
+                            ui.monospace(&line.message);
+                        });
+                    }
+                });
+        });
+}
@@ -0,0 +1,597 @@
+use std::collections::VecDeque;
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_egui::EguiContexts;
+
+use crate::gyro::{gyro_update, Source};
+use crate::plots::CHANNEL_NAME;
+use crate::spectrum::{dominant_peaks, Spectrum, Window};
+
+/// Samples the dynamic notch looks at per FFT, and how many new ones it
+/// waits for before looking again.
+const TRACKER_FFT: usize = 256;
+const TRACKER_HOP: usize = TRACKER_FFT / 4;
+
+/// How far the tracked centre moves towards a new peak per FFT.
+const TRACKER_SMOOTHING: f32 = 0.3;
+
+/// Biquad coefficients are recomputed once the sample rate has drifted by
+/// more than this fraction.
+const RATE_TOLERANCE: f32 = 0.01;
+
+/// One stage of a filter chain. Frequencies are in Hz.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FilterSpec {
+    /// First-order low-pass.
+    Pt1 {
+        cutoff: f32,
+    },
+    /// Two first-order low-passes in series.
+    Pt2 {
+        cutoff: f32,
+    },
+    /// Second-order low-pass.
+    Biquad {
+        cutoff: f32,
+        q: f32,
+    },
+    Notch {
+        center: f32,
+        q: f32,
+    },
+    /// Notch that follows the strongest peak between `min` and `max`.
+    DynamicNotch {
+        min: f32,
+        max: f32,
+        q: f32,
+    },
+}
+
+impl FilterSpec {
+    pub const DEFAULTS: [FilterSpec; 5] = [
+        FilterSpec::Pt1 { cutoff: 100. },
+        FilterSpec::Pt2 { cutoff: 100. },
+        FilterSpec::Biquad {
+            cutoff: 100.,
+            q: std::f32::consts::FRAC_1_SQRT_2,
+        },
+        FilterSpec::Notch {
+            center: 150.,
+            q: 3.,
+        },
+        FilterSpec::DynamicNotch {
+            min: 60.,
+            max: 400.,
+            q: 3.,
+        },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterSpec::Pt1 { .. } => "PT1",
+            FilterSpec::Pt2 { .. } => "PT2",
+            FilterSpec::Biquad { .. } => "Biquad",
+            FilterSpec::Notch { .. } => "Notch",
+            FilterSpec::DynamicNotch { .. } => "Dynamic notch",
+        }
+    }
+}
+
+/// Direct form I, so coefficients can change between samples without
+/// upsetting the state.
+#[derive(Clone, Default)]
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl Biquad {
+    fn set(&mut self, b: [f32; 3], a: [f32; 3]) {
+        self.b = b.map(|b| b / a[0]);
+        self.a = [a[1] / a[0], a[2] / a[0]];
+    }
+
+    fn set_lowpass(&mut self, cutoff: f32, q: f32, sample_rate: f32) {
+        let (cos, alpha) = omega(cutoff, q, sample_rate);
+        let b0 = (1. - cos) / 2.;
+        self.set([b0, 1. - cos, b0], [1. + alpha, -2. * cos, 1. - alpha]);
+    }
+
+    fn set_notch(&mut self, center: f32, q: f32, sample_rate: f32) {
+        let (cos, alpha) = omega(center, q, sample_rate);
+        self.set([1., -2. * cos, 1.], [1. + alpha, -2. * cos, 1. - alpha]);
+    }
+
+    fn apply(&mut self, x: f32) -> f32 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// Cosine of the normalised frequency and the bandwidth term of the RBJ
+/// cookbook formulas, kept below Nyquist.
+fn omega(freq: f32, q: f32, sample_rate: f32) -> (f32, f32) {
+    let w = TAU * freq.clamp(1., 0.45 * sample_rate) / sample_rate;
+    (w.cos(), w.sin() / (2. * q.max(0.05)))
+}
+
+fn pt1(state: &mut f32, x: f32, cutoff: f32, dt: f32) -> f32 {
+    let rc = 1. / (TAU * cutoff.max(0.1));
+    *state += dt / (rc + dt) * (x - *state);
+    *state
+}
+
+/// Finds the strongest peak in the recent input of a dynamic notch.
+struct PeakTracker {
+    spectrum: Spectrum,
+    recent: VecDeque<f32>,
+    fresh: usize,
+}
+
+impl PeakTracker {
+    fn new() -> Self {
+        Self {
+            spectrum: Spectrum::new(TRACKER_FFT, Window::Hann),
+            recent: VecDeque::with_capacity(TRACKER_FFT),
+            fresh: 0,
+        }
+    }
+
+    fn push(&mut self, x: f32, sample_rate: f32, min: f32, max: f32) -> Option<f32> {
+        if self.recent.len() == TRACKER_FFT {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(x);
+        self.fresh += 1;
+        if self.recent.len() < TRACKER_FFT || self.fresh < TRACKER_HOP {
+            return None;
+        }
+        self.fresh = 0;
+        let samples = self.recent.make_contiguous();
+        let psd = self.spectrum.periodogram(samples, sample_rate);
+        // strongest first, so the first one in band wins over anything
+        // stronger above it
+        dominant_peaks(&psd, TRACKER_FFT, sample_rate, min, usize::MAX)
+            .into_iter()
+            .map(|p| p.freq)
+            .find(|f| *f <= max)
+    }
+}
+
+/// A filter stage and its state.
+pub struct Filter {
+    spec: FilterSpec,
+    /// Smoothed from the sample timestamps.
+    sample_rate: f32,
+    /// The rate the biquad coefficients were computed for; 0 when stale.
+    designed_for: f32,
+    pt: [f32; 2],
+    biquad: Biquad,
+    tracker: Option<PeakTracker>,
+    /// Where a dynamic notch currently sits.
+    center: Option<f32>,
+}
+
+impl Filter {
+    pub fn new(spec: FilterSpec) -> Self {
+        let mut filter = Self {
+            spec,
+            sample_rate: 0.,
+            designed_for: 0.,
+            pt: [0.; 2],
+            biquad: Biquad::default(),
+            tracker: None,
+            center: None,
+        };
+        filter.set_spec(spec);
+        filter
+    }
+
+    pub fn spec(&self) -> FilterSpec {
+        self.spec
+    }
+
+    /// Changes parameters in place; the signal state is kept so that
+    /// dragging a value does not make the output jump.
+    pub fn set_spec(&mut self, spec: FilterSpec) {
+        if let FilterSpec::DynamicNotch { min, max, .. } = spec {
+            self.tracker.get_or_insert_with(PeakTracker::new);
+            self.center = Some(self.center.unwrap_or((min + max) / 2.).clamp(min, max));
+        } else {
+            self.tracker = None;
+            self.center = None;
+        }
+        self.spec = spec;
+        self.designed_for = 0.;
+    }
+
+    /// Centre frequency a dynamic notch is tracking.
+    pub fn center(&self) -> Option<f32> {
+        self.center
+    }
+
+    fn design(&mut self) {
+        let fs = self.sample_rate;
+        match self.spec {
+            FilterSpec::Biquad { cutoff, q } => self.biquad.set_lowpass(cutoff, q, fs),
+            FilterSpec::Notch { center, q } => self.biquad.set_notch(center, q, fs),
+            FilterSpec::DynamicNotch { q, .. } => {
+                let center = self.center.unwrap_or(fs / 4.);
+                self.biquad.set_notch(center, q, fs)
+            }
+            FilterSpec::Pt1 { .. } | FilterSpec::Pt2 { .. } => {}
+        }
+        self.designed_for = fs;
+    }
+
+    /// Filters one sample taken `dt` seconds after the previous one.
+    pub fn apply(&mut self, x: f32, dt: f32) -> f32 {
+        if dt <= 0. || !dt.is_finite() {
+            return x;
+        }
+        if self.sample_rate == 0. {
+            // start settled on the first value instead of ramping up from 0
+            self.sample_rate = 1. / dt;
+            self.pt = [x; 2];
+            self.biquad.x = [x; 2];
+            self.biquad.y = [x; 2];
+        } else {
+            self.sample_rate += 0.01 * (1. / dt - self.sample_rate);
+        }
+
+        match self.spec {
+            FilterSpec::Pt1 { cutoff } => return pt1(&mut self.pt[0], x, cutoff, dt),
+            FilterSpec::Pt2 { cutoff } => {
+                let y = pt1(&mut self.pt[0], x, cutoff, dt);
+                return pt1(&mut self.pt[1], y, cutoff, dt);
+            }
+            FilterSpec::DynamicNotch { min, max, .. } => {
+                let tracker = self.tracker.as_mut().unwrap();
+                if let Some(peak) = tracker.push(x, self.sample_rate, min, max) {
+                    let center = self.center.get_or_insert(peak);
+                    *center += TRACKER_SMOOTHING * (peak - *center);
+                    self.designed_for = 0.;
+                }
+            }
+            FilterSpec::Biquad { .. } | FilterSpec::Notch { .. } => {}
+        }
+
+        if (self.sample_rate - self.designed_for).abs() > RATE_TOLERANCE * self.sample_rate {
+            self.design();
+        }
+        self.biquad.apply(x)
+    }
+}
+
+/// Filters applied one after another to a single channel.
+#[derive(Default)]
+pub struct FilterChain {
+    pub filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    /// Matches the chain to `specs`, keeping the state of every stage that
+    /// is still of the same kind.
+    pub fn configure(&mut self, specs: &[FilterSpec]) {
+        self.filters.truncate(specs.len());
+        for (i, spec) in specs.iter().enumerate() {
+            match self.filters.get_mut(i) {
+                Some(f) if f.spec == *spec => {}
+                Some(f) if std::mem::discriminant(&f.spec) == std::mem::discriminant(spec) => {
+                    f.set_spec(*spec)
+                }
+                Some(f) => *f = Filter::new(*spec),
+                None => self.filters.push(Filter::new(*spec)),
+            }
+        }
+    }
+
+    pub fn apply(&mut self, x: f32, dt: f32) -> f32 {
+        self.filters.iter_mut().fold(x, |x, f| f.apply(x, dt))
+    }
+}
+
+/// What every source's channels are filtered with before any estimator
+/// sees them.
+#[derive(Resource, Default)]
+pub struct FilterSettings {
+    pub channels: [Vec<FilterSpec>; 6],
+    /// Channel being edited, index into `CHANNEL_NAME`.
+    pub selected: usize,
+    /// Edits apply to all three axes of the selected sensor.
+    pub linked: bool,
+}
+
+/// Filter state of one source.
+#[derive(Component, Default)]
+pub struct SourceFilters {
+    pub chains: [FilterChain; 6],
+}
+
+impl SourceFilters {
+    /// Filters the gyro and accelerometer channels of a sample in place,
+    /// using its `dt`.
+    pub fn apply(&mut self, v: &mut [f32]) {
+        let dt = v[12];
+        for (chain, x) in self.chains.iter_mut().zip(v.iter_mut()) {
+            *x = chain.apply(*x, dt);
+        }
+    }
+}
+
+pub struct FilterPlugin;
+
+impl Plugin for FilterPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(FilterSettings {
+            linked: true,
+            ..default()
+        })
+        .add_systems(Update, (filters_configure.before(gyro_update), filters_ui));
+    }
+}
+
+pub fn filters_configure(
+    mut commands: Commands,
+    settings: Res<FilterSettings>,
+    unfiltered: Query<Entity, (With<Source>, Without<SourceFilters>)>,
+    mut filtered: Query<&mut SourceFilters>,
+) {
+    for entity in unfiltered.iter() {
+        let mut filters = SourceFilters::default();
+        for (chain, specs) in filters.chains.iter_mut().zip(settings.channels.iter()) {
+            chain.configure(specs);
+        }
+        commands.entity(entity).insert(filters);
+    }
+    if settings.is_changed() {
+        for mut filters in filtered.iter_mut() {
+            for (chain, specs) in filters.chains.iter_mut().zip(settings.channels.iter()) {
+                chain.configure(specs);
+            }
+        }
+    }
+}
+
+fn hz(value: &mut f32) -> egui::DragValue<'_> {
+    egui::DragValue::new(value)
+        .speed(1.)
+        .clamp_range(1.0..=2000.0)
+        .suffix(" Hz")
+}
+
+fn q(value: &mut f32) -> egui::DragValue<'_> {
+    egui::DragValue::new(value)
+        .speed(0.05)
+        .clamp_range(0.1..=20.0)
+        .prefix("Q ")
+}
+
+fn spec_ui(ui: &mut egui::Ui, spec: &mut FilterSpec) {
+    match spec {
+        FilterSpec::Pt1 { cutoff } | FilterSpec::Pt2 { cutoff } => {
+            ui.add(hz(cutoff));
+        }
+        FilterSpec::Biquad { cutoff, q: quality } => {
+            ui.add(hz(cutoff));
+            ui.add(q(quality));
+        }
+        FilterSpec::Notch { center, q: quality } => {
+            ui.add(hz(center));
+            ui.add(q(quality));
+        }
+        FilterSpec::DynamicNotch {
+            min,
+            max,
+            q: quality,
+        } => {
+            ui.add(hz(min));
+            ui.label("to");
+            ui.add(hz(max));
+            ui.add(q(quality));
+            *max = max.max(*min);
+        }
+    }
+}
+
+pub fn filters_ui(
+    mut contexts: EguiContexts,
+    mut settings: ResMut<FilterSettings>,
+    sources: Query<(&Source, &SourceFilters)>,
+) {
+    let ctx = contexts.ctx_mut();
+    // only touch the resource when something was edited, so the chains are
+    // not reconfigured every frame
+    let mut edited = settings.channels[settings.selected].clone();
+    let (mut selected, mut linked) = (settings.selected, settings.linked);
+
+    egui::Window::new("Filters")
+        .default_pos([20., 160.])
+        .default_open(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("filter_channel")
+                    .selected_text(CHANNEL_NAME[selected])
+                    .show_ui(ui, |ui| {
+                        for (i, name) in CHANNEL_NAME.iter().enumerate() {
+                            ui.selectable_value(&mut selected, i, *name);
+                        }
+                    });
+                ui.checkbox(&mut linked, "all axes");
+            });
+            ui.separator();
+
+            let mut remove = None;
+            for (i, spec) in edited.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(spec.name());
+                    spec_ui(ui, spec);
+                    if ui.small_button("✖").clicked() {
+                        remove = Some(i);
+                    }
+                });
+                if matches!(spec, FilterSpec::DynamicNotch { .. }) {
+                    for (source, filters) in sources.iter() {
+                        let center = filters.chains[selected]
+                            .filters
+                            .get(i)
+                            .and_then(|f| f.center());
+                        if let Some(center) = center {
+                            ui.label(format!("  {}: {center:.1} Hz", source.label));
+                        }
+                    }
+                }
+            }
+            if let Some(i) = remove {
+                edited.remove(i);
+            }
+
+            ui.horizontal(|ui| {
+                for spec in FilterSpec::DEFAULTS {
+                    if ui.button(format!("+ {}", spec.name())).clicked() {
+                        edited.push(spec);
+                    }
+                }
+            });
+        });
+
+    if selected != settings.selected || linked != settings.linked {
+        settings.selected = selected;
+        settings.linked = linked;
+        return;
+    }
+    if edited == settings.channels[selected] {
+        return;
+    }
+    let axes = if linked {
+        let sensor = selected / 3 * 3;
+        sensor..sensor + 3
+    } else {
+        selected..selected + 1
+    };
+    for channel in axes {
+        settings.channels[channel] = edited.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f32 = 1000.;
+
+    fn sine(freq: f32, i: usize) -> f32 {
+        (TAU * freq * i as f32 / FS).sin()
+    }
+
+    /// Output over input amplitude for a sine, once the filter has settled.
+    fn gain(spec: FilterSpec, freq: f32) -> f32 {
+        let mut filter = Filter::new(spec);
+        let (settle, measure) = (2000, 2000);
+        let mut peak = 0f32;
+        for i in 0..settle + measure {
+            let y = filter.apply(sine(freq, i), 1. / FS);
+            if i >= settle {
+                peak = peak.max(y.abs());
+            }
+        }
+        peak
+    }
+
+    fn assert_near(found: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (found - expected).abs() < tolerance,
+            "{found} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn pt1_step_reaches_63_percent_after_one_time_constant() {
+        let cutoff = 10.;
+        let mut filter = Filter::new(FilterSpec::Pt1 { cutoff });
+        filter.apply(0., 1. / FS);
+        let tau = (FS / (TAU * cutoff)).round() as usize;
+        let mut y = 0.;
+        for _ in 0..tau {
+            y = filter.apply(1., 1. / FS);
+        }
+        assert_near(y, 1. - (-1f32).exp(), 0.02);
+        for _ in 0..20 * tau {
+            y = filter.apply(1., 1. / FS);
+        }
+        assert_near(y, 1., 1e-4);
+    }
+
+    #[test]
+    fn first_order_low_passes_are_3db_down_at_cutoff() {
+        let cutoff = 20.;
+        let pt1 = gain(FilterSpec::Pt1 { cutoff }, cutoff);
+        assert_near(pt1, 0.707, 0.03);
+        // two of them in series
+        assert_near(gain(FilterSpec::Pt2 { cutoff }, cutoff), pt1 * pt1, 0.01);
+        assert!(gain(FilterSpec::Pt1 { cutoff }, 10. * cutoff) < 0.12);
+    }
+
+    #[test]
+    fn biquad_low_pass_response() {
+        let spec = FilterSpec::Biquad {
+            cutoff: 50.,
+            q: std::f32::consts::FRAC_1_SQRT_2,
+        };
+        assert_near(gain(spec, 5.), 1., 0.01);
+        assert_near(gain(spec, 50.), 0.707, 0.02);
+        // second order: a hundredth a decade above the cutoff
+        assert!(gain(spec, 400.) < 0.03);
+
+        let mut filter = Filter::new(spec);
+        filter.apply(0., 1. / FS);
+        let y = (0..1000).map(|_| filter.apply(1., 1. / FS)).last().unwrap();
+        assert_near(y, 1., 1e-3);
+    }
+
+    #[test]
+    fn notch_removes_its_centre_only() {
+        let spec = FilterSpec::Notch {
+            center: 150.,
+            q: 3.,
+        };
+        assert!(gain(spec, 150.) < 0.02);
+        assert_near(gain(spec, 20.), 1., 0.02);
+        assert_near(gain(spec, 450.), 1., 0.05);
+    }
+
+    #[test]
+    fn tracker_finds_the_strongest_peak_in_band() {
+        let mut tracker = PeakTracker::new();
+        let mut found = None;
+        for i in 0..TRACKER_FFT * 2 {
+            // the stronger tone above the band is not what is tracked
+            let x = sine(150., i) + 3. * sine(450., i);
+            found = tracker.push(x, FS, 60., 400.).or(found);
+        }
+        assert_near(found.unwrap(), 150., FS / TRACKER_FFT as f32);
+    }
+
+    #[test]
+    fn dynamic_notch_settles_on_the_tone() {
+        let spec = FilterSpec::DynamicNotch {
+            min: 60.,
+            max: 400.,
+            q: 3.,
+        };
+        let mut filter = Filter::new(spec);
+        for i in 0..5000 {
+            filter.apply(sine(220., i), 1. / FS);
+        }
+        assert_near(filter.center().unwrap(), 220., 2.);
+        assert!(gain(spec, 220.) < 0.1);
+    }
+}
@@ -0,0 +1,303 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_egui::EguiContexts;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, EstimatorConfig};
+
+use super::{DroneColor, DroneLabel, DroneVariant, GyroComponent, GyroState, HomePosition, Source};
+
+/// Gap between the rows of drones belonging to different sources.
+const SOURCE_SPACING: f32 = 7.;
+
+/// One displayed drone.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DroneSpec {
+    pub label: String,
+    pub estimator: DroneVariant,
+    /// Share of the accelerometer in the `both` estimator, the configured
+    /// one when left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acc_weight: Option<f32>,
+    /// `#rrggbb`.
+    pub color: String,
+    pub position: [f32; 3],
+    /// Label of the source the drone is driven by.
+    pub source: String,
+}
+
+/// The drones to spawn at startup. Left empty, every source gets the usual
+/// row of one drone per estimator.
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct Layout {
+    #[serde(default, rename = "drone")]
+    pub drones: Vec<DroneSpec>,
+}
+
+/// Where the layout is loaded from and saved to.
+#[derive(Resource)]
+pub struct LayoutFile(pub PathBuf);
+
+impl Default for LayoutFile {
+    fn default() -> Self {
+        Self("layout.toml".into())
+    }
+}
+
+impl Layout {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// A row per source: fused in the middle, gyro-only and
+    /// accelerometer-only either side, and the device's own attitude where
+    /// the source reports one.
+    pub fn per_source<'a>(sources: impl Iterator<Item = &'a Source>) -> Self {
+        let mut drones = vec![];
+        for (row, source) in sources.enumerate() {
+            let y = -(row as f32) * SOURCE_SPACING;
+            let mut variants = vec![
+                (Color::RED, 0., DroneVariant::Both),
+                (Color::BLUE, 7., DroneVariant::Gyro),
+                (Color::YELLOW, -7., DroneVariant::Acc),
+            ];
+            if source.spec.provides_attitude() {
+                variants.push((Color::GREEN, 14., DroneVariant::Reference));
+            }
+            for (color, x, estimator) in variants {
+                drones.push(DroneSpec {
+                    label: format!("{} {estimator:?}", source.label),
+                    estimator,
+                    acc_weight: None,
+                    color: color_hex(color),
+                    position: [x, y, 0.],
+                    source: source.label.clone(),
+                });
+            }
+        }
+        Self { drones }
+    }
+}
+
+pub fn color_hex(color: Color) -> String {
+    let [r, g, b, _] = color.as_rgba_u8();
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Spawns a bare transform carrying the attitude; the model is attached as a
+/// child by the model plugin.
+pub fn spawn_drone(
+    commands: &mut Commands,
+    spec: &DroneSpec,
+    source: Entity,
+    estimator: &EstimatorConfig,
+) -> Entity {
+    let color = Color::hex(spec.color.trim_start_matches('#')).unwrap_or(Color::WHITE);
+    commands
+        .spawn((
+            SpatialBundle::from_transform(Transform::from_translation(spec.position.into())),
+            DroneColor(color),
+            DroneLabel(spec.label.clone()),
+            HomePosition(spec.position.into()),
+            GyroComponent {
+                acc_weight: spec.acc_weight.unwrap_or(estimator.acc_weight),
+                calibration_samples: estimator.calibration_samples,
+                state: GyroState::Calibration(vec![]),
+                x: None,
+                y: None,
+                z: None,
+                signy: 1.0,
+                offset: (0.0, 0.0, 0.0),
+                variant: spec.estimator,
+                source,
+                attitude: Quat::IDENTITY,
+                tare: Quat::IDENTITY,
+                realign: false,
+            },
+        ))
+        .id()
+}
+
+pub fn gyro_spawn(
+    mut commands: Commands,
+    mut layout: ResMut<Layout>,
+    config: Res<Config>,
+    sources: Query<(Entity, &Source)>,
+) {
+    if layout.drones.is_empty() {
+        *layout = Layout::per_source(sources.iter().map(|(_, s)| s));
+    }
+    for spec in layout.drones.iter() {
+        match sources.iter().find(|(_, s)| s.label == spec.source) {
+            Some((source, _)) => {
+                spawn_drone(&mut commands, spec, source, &config.estimator);
+            }
+            None => warn!(
+                "layout: no source labelled `{}` for drone `{}`",
+                spec.source, spec.label
+            ),
+        }
+    }
+}
+
+/// Fields of the drone about to be added.
+pub struct NewDrone {
+    spec: DroneSpec,
+    color: [f32; 3],
+}
+
+impl Default for NewDrone {
+    fn default() -> Self {
+        Self {
+            spec: DroneSpec {
+                label: "drone".to_owned(),
+                estimator: DroneVariant::Both,
+                acc_weight: None,
+                color: String::new(),
+                position: [0.; 3],
+                source: String::new(),
+            },
+            color: [1., 1., 1.],
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn layout_ui(
+    mut contexts: EguiContexts,
+    mut commands: Commands,
+    file: Res<LayoutFile>,
+    config: Res<Config>,
+    mut new: Local<NewDrone>,
+    mut status: Local<String>,
+    sources: Query<(Entity, &Source)>,
+    drones: Query<(
+        Entity,
+        &DroneLabel,
+        &DroneColor,
+        &HomePosition,
+        &GyroComponent,
+    )>,
+) {
+    egui::Window::new("Layout")
+        .default_pos([20., 320.])
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Grid::new("layout_drones")
+                .striped(true)
+                .show(ui, |ui| {
+                    for (entity, label, _, _, gyro) in drones.iter() {
+                        let source = sources.get(gyro.source).map_or("?", |(_, s)| &s.label);
+                        ui.label(&label.0);
+                        ui.label(format!("{:?}", gyro.variant));
+                        ui.label(source);
+                        if ui.small_button("Remove").clicked() {
+                            commands.entity(entity).despawn_recursive();
+                        }
+                        ui.end_row();
+                    }
+                });
+
+            ui.separator();
+            let new = &mut *new;
+            egui::Grid::new("layout_new").show(ui, |ui| {
+                ui.label("label");
+                ui.text_edit_singleline(&mut new.spec.label);
+                ui.end_row();
+                ui.label("estimator");
+                egui::ComboBox::from_id_source("layout_estimator")
+                    .selected_text(format!("{:?}", new.spec.estimator))
+                    .show_ui(ui, |ui| {
+                        for v in [
+                            DroneVariant::Both,
+                            DroneVariant::Gyro,
+                            DroneVariant::Acc,
+                            DroneVariant::Reference,
+                        ] {
+                            ui.selectable_value(&mut new.spec.estimator, v, format!("{v:?}"));
+                        }
+                    });
+                ui.end_row();
+                if new.spec.estimator == DroneVariant::Both {
+                    ui.label("acc weight");
+                    let weight = new
+                        .spec
+                        .acc_weight
+                        .get_or_insert(config.estimator.acc_weight);
+                    ui.add(egui::Slider::new(weight, 0.0..=1.0));
+                    ui.end_row();
+                }
+                ui.label("source");
+                egui::ComboBox::from_id_source("layout_source")
+                    .selected_text(&new.spec.source)
+                    .show_ui(ui, |ui| {
+                        for (_, source) in sources.iter() {
+                            let label = source.label.clone();
+                            ui.selectable_value(&mut new.spec.source, label, &source.label);
+                        }
+                    });
+                ui.end_row();
+                ui.label("colour");
+                ui.color_edit_button_rgb(&mut new.color);
+                ui.end_row();
+                ui.label("position");
+                ui.horizontal(|ui| {
+                    for p in new.spec.position.iter_mut() {
+                        ui.add(egui::DragValue::new(p).speed(0.1));
+                    }
+                });
+                ui.end_row();
+            });
+            let source = sources.iter().find(|(_, s)| s.label == new.spec.source);
+            if ui
+                .add_enabled(source.is_some(), egui::Button::new("Add"))
+                .clicked()
+            {
+                let [r, g, b] = new.color;
+                new.spec.color = color_hex(Color::rgb(r, g, b));
+                spawn_drone(
+                    &mut commands,
+                    &new.spec,
+                    source.unwrap().0,
+                    &config.estimator,
+                );
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button(format!("Save to {}", file.0.display())).clicked() {
+                    // a drone whose source has gone cannot be loaded again
+                    let mut skipped = vec![];
+                    let mut layout = Layout { drones: vec![] };
+                    for (_, label, color, home, gyro) in drones.iter() {
+                        let Ok((_, source)) = sources.get(gyro.source) else {
+                            skipped.push(label.0.as_str());
+                            continue;
+                        };
+                        layout.drones.push(DroneSpec {
+                            label: label.0.clone(),
+                            estimator: gyro.variant,
+                            acc_weight: Some(gyro.acc_weight),
+                            color: color_hex(color.0),
+                            position: home.0.into(),
+                            source: source.label.clone(),
+                        });
+                    }
+                    *status = match layout.save(&file.0) {
+                        Ok(()) if skipped.is_empty() => "saved".to_owned(),
+                        Ok(()) => format!("saved, without {} (no source)", skipped.join(", ")),
+                        Err(e) => e,
+                    };
+                }
+                ui.label(&*status);
+            });
+        });
+}
@@ -0,0 +1,604 @@
+use std::fmt::Display;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use bevy::log::error;
+use crossbeam_channel::Receiver;
+
+use super::{open_failed, CHANNEL_CAPACITY};
+
+const STX_V1: u8 = 0xFE;
+const STX_V2: u8 = 0xFD;
+const SIGNED: u8 = 0x01;
+
+const HEARTBEAT: u32 = 0;
+const SCALED_IMU: u32 = 26;
+const RAW_IMU: u32 = 27;
+const ATTITUDE: u32 = 30;
+const ATTITUDE_QUATERNION: u32 = 31;
+const COMMAND_LONG: u32 = 76;
+
+const MAV_CMD_SET_MESSAGE_INTERVAL: u16 = 511;
+const MAV_COMP_ID_AUTOPILOT1: u8 = 1;
+
+/// Per-axis signs taking forward-right-down readings to the device axes.
+const FRD_TO_DEVICE: [f32; 3] = [1., -1., -1.];
+
+/// What we identify as when asking the vehicle for streams.
+const GCS_SYSID: u8 = 255;
+const GCS_COMPID: u8 = 190;
+
+/// CRC seed and full (untruncated) payload length of every message we know
+/// how to check.
+fn message_info(msgid: u32) -> Option<(u8, usize)> {
+    match msgid {
+        HEARTBEAT => Some((50, 9)),
+        SCALED_IMU => Some((170, 24)),
+        RAW_IMU => Some((144, 29)),
+        ATTITUDE => Some((39, 28)),
+        ATTITUDE_QUATERNION => Some((246, 48)),
+        COMMAND_LONG => Some((152, 33)),
+        _ => None,
+    }
+}
+
+fn crc_accumulate(crc: u16, byte: u8) -> u16 {
+    let mut tmp = byte ^ (crc & 0xff) as u8;
+    tmp ^= tmp << 4;
+    let tmp = tmp as u16;
+    (crc >> 8) ^ (tmp << 8) ^ (tmp << 3) ^ (tmp >> 4)
+}
+
+fn crc(bytes: &[u8], extra: u8) -> u16 {
+    let crc = bytes.iter().fold(0xFFFF, |crc, b| crc_accumulate(crc, *b));
+    crc_accumulate(crc, extra)
+}
+
+pub struct MavMessage {
+    pub sysid: u8,
+    pub compid: u8,
+    pub msgid: u32,
+    /// Zero-extended to the full length, undoing MAVLink 2 truncation.
+    pub payload: Vec<u8>,
+}
+
+/// Splits a MAVLink v1/v2 byte stream into checked messages. Messages we have
+/// no CRC seed for are passed over whole as soon as their header is in, so a
+/// busy link full of them holds up nothing that follows.
+#[derive(Default)]
+pub struct MavlinkParser {
+    buf: Vec<u8>,
+    /// What is left of a message being passed over, still to arrive.
+    skip: usize,
+}
+
+impl MavlinkParser {
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn next_message(&mut self) -> Option<MavMessage> {
+        loop {
+            let skipped = self.skip.min(self.buf.len());
+            self.buf.drain(..skipped);
+            self.skip -= skipped;
+            if self.skip > 0 {
+                return None;
+            }
+
+            let start = self.buf.iter().position(|b| *b == STX_V1 || *b == STX_V2);
+            self.buf.drain(..start.unwrap_or(self.buf.len()));
+            if self.buf.len() < 2 {
+                return None;
+            }
+
+            let len = self.buf[1] as usize;
+            let (header, msgid, sysid, compid, signature) = if self.buf[0] == STX_V2 {
+                if self.buf.len() < 10 {
+                    return None;
+                }
+                // flags nobody has defined yet mean a false start byte
+                if self.buf[2] & !SIGNED != 0 {
+                    self.buf.drain(..1);
+                    continue;
+                }
+                let msgid = u32::from_le_bytes([self.buf[7], self.buf[8], self.buf[9], 0]);
+                let signature = if self.buf[2] & SIGNED != 0 { 13 } else { 0 };
+                (10, msgid, self.buf[5], self.buf[6], signature)
+            } else {
+                if self.buf.len() < 6 {
+                    return None;
+                }
+                (6, self.buf[5] as u32, self.buf[3], self.buf[4], 0)
+            };
+
+            let total = header + len + 2 + signature;
+            let Some((extra, full_len)) = message_info(msgid) else {
+                // nothing to check it against, so its header is taken at
+                // its word
+                self.skip = total;
+                continue;
+            };
+            if self.buf.len() < total {
+                return None;
+            }
+
+            let checksum = u16::from_le_bytes([self.buf[header + len], self.buf[header + len + 1]]);
+            if crc(&self.buf[1..header + len], extra) != checksum {
+                // a false start byte, resync on the next one
+                self.buf.drain(..1);
+                continue;
+            }
+
+            let mut payload = self.buf[header..header + len].to_vec();
+            payload.resize(payload.len().max(full_len), 0);
+            self.buf.drain(..total);
+            return Some(MavMessage {
+                sysid,
+                compid,
+                msgid,
+                payload,
+            });
+        }
+    }
+}
+
+pub fn encode_v2(seq: u8, msgid: u32, payload: &[u8]) -> Vec<u8> {
+    let (extra, _) = message_info(msgid).expect("no CRC seed for message");
+    let id = msgid.to_le_bytes();
+    let mut frame = vec![
+        STX_V2,
+        payload.len() as u8,
+        0,
+        0,
+        seq,
+        GCS_SYSID,
+        GCS_COMPID,
+        id[0],
+        id[1],
+        id[2],
+    ];
+    frame.extend_from_slice(payload);
+    let checksum = crc(&frame[1..], extra);
+    frame.extend_from_slice(&checksum.to_le_bytes());
+    frame
+}
+
+fn set_message_interval(seq: u8, target: (u8, u8), msgid: u32, interval_us: f32) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(33);
+    for param in [msgid as f32, interval_us, 0., 0., 0., 0., 0.] {
+        payload.extend_from_slice(&param.to_le_bytes());
+    }
+    payload.extend_from_slice(&MAV_CMD_SET_MESSAGE_INTERVAL.to_le_bytes());
+    payload.extend_from_slice(&[target.0, target.1, 0]);
+    encode_v2(seq, COMMAND_LONG, &payload)
+}
+
+fn i16_at(payload: &[u8], offset: usize) -> f32 {
+    i16::from_le_bytes([payload[offset], payload[offset + 1]]) as f32
+}
+
+fn f32_at(payload: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes(payload[offset..offset + 4].try_into().unwrap())
+}
+
+/// Turns IMU and attitude messages into the 13-float sample layout the
+/// device frames use: gyro (deg/s), acc (g), mag (gauss), the vehicle's own
+/// roll/pitch/yaw (deg) in the extra channels, and the time since the
+/// previous sample by the vehicle clock.
+///
+/// MAVLink IMUs report in the body frame, forward-right-down, while our
+/// devices point z up: a level vehicle at rest reads +1 g on z. Forward stays
+/// x, so y and z swap sign.
+#[derive(Default)]
+struct Assembler {
+    attitude: [f32; 3],
+    last_imu_us: Option<u64>,
+    last_attitude_ms: Option<u32>,
+    /// Whichever IMU message showed up first; mixing two of them would
+    /// integrate every rotation twice.
+    imu_msgid: Option<u32>,
+}
+
+impl Assembler {
+    fn handle(&mut self, msg: &MavMessage) -> Option<Vec<f32>> {
+        let p = &msg.payload;
+        match msg.msgid {
+            // ArduPilot fills RAW_IMU with the same mG / mrad/s / mgauss
+            // units SCALED_IMU uses, so both decode alike
+            SCALED_IMU | RAW_IMU => {
+                if *self.imu_msgid.get_or_insert(msg.msgid) != msg.msgid {
+                    return None;
+                }
+                let (time_us, base) = if msg.msgid == SCALED_IMU {
+                    (
+                        u32::from_le_bytes(p[0..4].try_into().unwrap()) as u64 * 1000,
+                        4,
+                    )
+                } else {
+                    (u64::from_le_bytes(p[0..8].try_into().unwrap()), 8)
+                };
+                let dt = self
+                    .last_imu_us
+                    .map_or(0., |last| time_us.saturating_sub(last) as f32 / 1.0e6);
+                self.last_imu_us = Some(time_us);
+
+                // acc, gyro and mag triplets, from forward-right-down
+                let axes = |offset: usize, scale: f32| {
+                    FRD_TO_DEVICE
+                        .into_iter()
+                        .enumerate()
+                        .map(move |(i, sign)| sign * i16_at(p, base + offset + 2 * i) / scale)
+                };
+                let mut sample = Vec::with_capacity(13);
+                sample.extend(axes(6, 1000.).map(f32::to_degrees));
+                sample.extend(axes(0, 1000.));
+                sample.extend(axes(12, 1000.));
+                sample.extend_from_slice(&self.attitude);
+                sample.push(dt);
+                Some(sample)
+            }
+            ATTITUDE | ATTITUDE_QUATERNION => {
+                let time_ms = u32::from_le_bytes(p[0..4].try_into().unwrap());
+                let (roll, pitch, yaw) = if msg.msgid == ATTITUDE {
+                    (f32_at(p, 4), f32_at(p, 8), f32_at(p, 12))
+                } else {
+                    let (w, x, y, z) = (f32_at(p, 4), f32_at(p, 8), f32_at(p, 12), f32_at(p, 16));
+                    (
+                        f32::atan2(2. * (w * x + y * z), 1. - 2. * (x * x + y * y)),
+                        (2. * (w * y - z * x)).clamp(-1., 1.).asin(),
+                        f32::atan2(2. * (w * z + x * y), 1. - 2. * (y * y + z * z)),
+                    )
+                };
+                self.attitude = [roll.to_degrees(), pitch.to_degrees(), yaw.to_degrees()];
+
+                let dt = self
+                    .last_attitude_ms
+                    .map_or(0., |last| time_ms.wrapping_sub(last) as f32 / 1.0e3);
+                self.last_attitude_ms = Some(time_ms);
+
+                // vehicles that only stream attitude still get a drone that moves
+                if self.imu_msgid.is_some() {
+                    None
+                } else {
+                    let mut sample = vec![0.; 9];
+                    sample.extend_from_slice(&self.attitude);
+                    sample.push(dt);
+                    Some(sample)
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Serial port or TCP stream, or a UDP socket that answers whoever spoke
+/// to it last.
+enum Link {
+    Stream(Box<dyn ReadWrite>),
+    Udp {
+        socket: UdpSocket,
+        peer: Option<SocketAddr>,
+    },
+}
+
+trait ReadWrite: Read + Write + Send {}
+impl<T: Read + Write + Send> ReadWrite for T {}
+
+impl Link {
+    fn recv(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Link::Stream(s) => match s.read(buf)? {
+                0 => Err(ErrorKind::UnexpectedEof.into()),
+                n => Ok(n),
+            },
+            Link::Udp { socket, peer } => {
+                let (n, from) = socket.recv_from(buf)?;
+                *peer = Some(from);
+                Ok(n)
+            }
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            Link::Stream(s) => s.write_all(data),
+            Link::Udp {
+                socket,
+                peer: Some(peer),
+            } => socket.send_to(data, *peer).map(|_| ()),
+            Link::Udp { peer: None, .. } => Ok(()),
+        }
+    }
+}
+
+fn spawn_mavlink(name: String, mut link: Link) -> Receiver<Vec<f32>> {
+    let (tx, rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+
+    std::thread::spawn(move || {
+        let mut parser = MavlinkParser::default();
+        let mut assembler = Assembler::default();
+        let mut buf = [0u8; 2048];
+        let mut seq = 0u8;
+        let mut requested = false;
+        loop {
+            let n = match link.recv(&mut buf) {
+                Ok(n) => n,
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                    continue
+                }
+                Err(e) => {
+                    error!("{name}: {e}");
+                    break;
+                }
+            };
+            parser.feed(&buf[..n]);
+
+            while let Some(msg) = parser.next_message() {
+                if msg.compid != MAV_COMP_ID_AUTOPILOT1 || msg.sysid == GCS_SYSID {
+                    continue;
+                }
+                if !requested {
+                    // ask for 50 Hz of everything we decode; best effort,
+                    // vehicles that already stream them lose nothing
+                    for msgid in [SCALED_IMU, RAW_IMU, ATTITUDE, ATTITUDE_QUATERNION] {
+                        let frame =
+                            set_message_interval(seq, (msg.sysid, msg.compid), msgid, 20000.);
+                        seq = seq.wrapping_add(1);
+                        let _ = link.send(&frame);
+                    }
+                    requested = true;
+                }
+                if let Some(sample) = assembler.handle(&msg) {
+                    if tx.send(sample).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    rx
+}
+
+pub fn open_mavlink(port_path: &std::path::Path, baudrate: u32) -> Receiver<Vec<f32>> {
+    let name = port_path.to_string_lossy().into_owned();
+    match serialport::new(&name, baudrate)
+        .timeout(Duration::from_millis(500))
+        .open()
+    {
+        Ok(port) => spawn_mavlink(name, Link::Stream(Box::new(port))),
+        Err(e) => open_failed(name, e),
+    }
+}
+
+pub fn open_mavlink_tcp(addr: impl ToSocketAddrs + Display) -> Receiver<Vec<f32>> {
+    match TcpStream::connect(&addr) {
+        Ok(stream) => spawn_mavlink(addr.to_string(), Link::Stream(Box::new(stream))),
+        Err(e) => open_failed(addr, e),
+    }
+}
+
+/// Binds `addr` and waits for the vehicle to send to it, e.g. `0.0.0.0:14550`
+/// for SITL.
+pub fn open_mavlink_udp(addr: impl ToSocketAddrs + Display) -> Receiver<Vec<f32>> {
+    match UdpSocket::bind(&addr) {
+        Ok(socket) => spawn_mavlink(addr.to_string(), Link::Udp { socket, peer: None }),
+        Err(e) => open_failed(addr, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::Vec3;
+
+    use super::*;
+    use crate::gyro::acc_to_model;
+
+    fn attitude_payload() -> Vec<u8> {
+        let mut payload = vec![];
+        payload.extend_from_slice(&1234u32.to_le_bytes());
+        for value in [0.1f32, -0.2, 1.5, 0.01, 0.02, 0.03] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        payload
+    }
+
+    fn encode_v1(seq: u8, msgid: u8, payload: &[u8]) -> Vec<u8> {
+        let (extra, _) = message_info(msgid as u32).unwrap();
+        let mut frame = vec![STX_V1, payload.len() as u8, seq, 1, 1, msgid];
+        frame.extend_from_slice(payload);
+        let checksum = crc(&frame[1..], extra);
+        frame.extend_from_slice(&checksum.to_le_bytes());
+        frame
+    }
+
+    fn parse(bytes: &[u8]) -> Vec<MavMessage> {
+        let mut parser = MavlinkParser::default();
+        parser.feed(bytes);
+        std::iter::from_fn(|| parser.next_message()).collect()
+    }
+
+    #[test]
+    fn crc_check_value() {
+        // CRC-16/MCRF4XX, which MAVLink's X.25 variant is
+        let crc = b"123456789"
+            .iter()
+            .fold(0xFFFF, |crc, b| crc_accumulate(crc, *b));
+        assert_eq!(crc, 0x6F91);
+    }
+
+    #[test]
+    fn round_trips_v2() {
+        let payload = attitude_payload();
+        let messages = parse(&encode_v2(7, ATTITUDE, &payload));
+        assert_eq!(messages.len(), 1);
+        let msg = &messages[0];
+        assert_eq!(
+            (msg.sysid, msg.compid, msg.msgid),
+            (GCS_SYSID, GCS_COMPID, ATTITUDE)
+        );
+        assert_eq!(msg.payload, payload);
+    }
+
+    #[test]
+    fn parses_v1() {
+        let payload = attitude_payload();
+        let messages = parse(&encode_v1(3, ATTITUDE as u8, &payload));
+        assert_eq!(messages.len(), 1);
+        assert_eq!((messages[0].sysid, messages[0].msgid), (1, ATTITUDE));
+        assert_eq!(messages[0].payload, payload);
+    }
+
+    #[test]
+    fn zero_extends_truncated_v2_payloads() {
+        let mut payload = attitude_payload();
+        payload[24..].fill(0);
+        // MAVLink 2 drops trailing zeros on the wire
+        let messages = parse(&encode_v2(0, ATTITUDE, &payload[..24]));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload, payload);
+    }
+
+    #[test]
+    fn skips_the_signature_of_signed_frames() {
+        let payload = attitude_payload();
+        let mut frame = encode_v2(0, ATTITUDE, &payload);
+        frame.truncate(frame.len() - 2);
+        frame[2] |= SIGNED;
+        let checksum = crc(&frame[1..], message_info(ATTITUDE).unwrap().0);
+        frame.extend_from_slice(&checksum.to_le_bytes());
+        // the signature follows the checksum and is not checked
+        frame.extend_from_slice(&[0xAA; 13]);
+        frame.extend(encode_v2(1, HEARTBEAT, &[0; 9]));
+        let messages = parse(&frame);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].payload, payload);
+        assert_eq!(messages[1].msgid, HEARTBEAT);
+    }
+
+    /// A frame of a message we have no CRC seed for.
+    fn unknown_v2(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![STX_V2, payload.len() as u8, 0, 0, 0, 1, 1, 0x34, 0x12, 0];
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(&[0x12, 0x34]);
+        frame
+    }
+
+    #[test]
+    fn skips_garbage_before_a_frame() {
+        // the stray STX_V1 claims a HEARTBEAT that fails its checksum, the
+        // stray STX_V2 flags nobody has defined
+        let mut bytes = vec![0x00, 0x42, STX_V1, 0x01, STX_V2];
+        bytes.extend(encode_v2(0, ATTITUDE, &attitude_payload()));
+        let messages = parse(&bytes);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].msgid, ATTITUDE);
+    }
+
+    #[test]
+    fn skips_unknown_messages_whole() {
+        // a known frame inside the payload is not mistaken for a real one
+        let mut bytes = unknown_v2(&encode_v2(9, HEARTBEAT, &[0; 9]));
+        for seq in 0..4 {
+            bytes.extend(encode_v2(seq, ATTITUDE, &attitude_payload()));
+        }
+        let messages = parse(&bytes);
+        assert_eq!(messages.len(), 4);
+        assert!(messages.iter().all(|m| m.msgid == ATTITUDE));
+    }
+
+    #[test]
+    fn unknown_messages_do_not_hold_up_the_next() {
+        let unknown = unknown_v2(&[0xAA; 200]);
+        let mut parser = MavlinkParser::default();
+        // decided on from the header alone
+        parser.feed(&unknown[..40]);
+        assert!(parser.next_message().is_none());
+        parser.feed(&unknown[40..]);
+        parser.feed(&encode_v2(0, ATTITUDE, &attitude_payload()));
+        assert_eq!(parser.next_message().map(|m| m.msgid), Some(ATTITUDE));
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_frame() {
+        let frame = encode_v2(0, ATTITUDE, &attitude_payload());
+        let mut parser = MavlinkParser::default();
+        parser.feed(&frame[..20]);
+        assert!(parser.next_message().is_none());
+        parser.feed(&frame[20..]);
+        assert!(parser.next_message().is_some());
+    }
+
+    fn scaled_imu(time_ms: u32, acc: [i16; 3], gyro: [i16; 3], mag: [i16; 3]) -> MavMessage {
+        let mut payload = time_ms.to_le_bytes().to_vec();
+        for value in acc.iter().chain(&gyro).chain(&mag) {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        payload.resize(message_info(SCALED_IMU).unwrap().1, 0);
+        MavMessage {
+            sysid: 1,
+            compid: 1,
+            msgid: SCALED_IMU,
+            payload,
+        }
+    }
+
+    fn attitude(time_ms: u32, roll: f32, pitch: f32, yaw: f32) -> MavMessage {
+        let mut payload = time_ms.to_le_bytes().to_vec();
+        for value in [roll, pitch, yaw, 0., 0., 0.] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        MavMessage {
+            sysid: 1,
+            compid: 1,
+            msgid: ATTITUDE,
+            payload,
+        }
+    }
+
+    #[test]
+    fn level_imu_reads_up_in_device_axes() {
+        let mut assembler = Assembler::default();
+        // forward-right-down: at rest gravity's reaction points up, -z
+        let msg = scaled_imu(1000, [0, 0, -1000], [100, 200, 300], [10, 20, 30]);
+        let sample = assembler.handle(&msg).unwrap();
+        assert_eq!(&sample[3..6], [0., 0., 1.]);
+        assert_eq!(acc_to_model(&sample), Vec3::Y);
+        let degrees = |mrad: f32| (mrad / 1000.).to_degrees();
+        assert_eq!(
+            &sample[0..3],
+            [degrees(100.), -degrees(200.), -degrees(300.)]
+        );
+        assert_eq!(&sample[6..9], [0.01, -0.02, -0.03]);
+        assert_eq!(sample[12], 0.);
+
+        let msg = scaled_imu(1020, [0, 0, -1000], [0; 3], [0; 3]);
+        let sample = assembler.handle(&msg).unwrap();
+        assert!((sample[12] - 0.02).abs() < 1e-6);
+    }
+
+    #[test]
+    fn attitude_rides_along_with_the_imu() {
+        let mut assembler = Assembler::default();
+        // before any IMU message, attitude alone still makes samples
+        let alone = assembler.handle(&attitude(500, 0.1, -0.2, 1.)).unwrap();
+        assert_eq!(&alone[0..9], [0.; 9]);
+        assert_eq!(
+            &alone[9..12],
+            [
+                0.1f32.to_degrees(),
+                (-0.2f32).to_degrees(),
+                1f32.to_degrees()
+            ]
+        );
+
+        assembler.handle(&scaled_imu(1000, [0, 0, -1000], [0; 3], [0; 3]));
+        assert!(assembler.handle(&attitude(1010, 0.3, 0., 0.)).is_none());
+        let sample = assembler
+            .handle(&scaled_imu(1020, [0, 0, -1000], [0; 3], [0; 3]))
+            .unwrap();
+        assert_eq!(&sample[9..12], [0.3f32.to_degrees(), 0., 0.]);
+    }
+}
@@ -1,67 +1,316 @@
 use std::f32::consts::PI;
 use std::io::{BufRead, BufReader, Read};
-use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 
 use bevy::prelude::*;
-use crossbeam_channel::Receiver;
+use crossbeam_channel::{Receiver, TryRecvError};
+use serde::{Deserialize, Serialize};
 
-#[derive(Resource)]
+use crate::config::Config;
+use crate::filter::SourceFilters;
+
+mod layout;
+mod mavlink;
+mod msp;
+mod server;
+pub use layout::{color_hex, gyro_spawn, layout_ui, spawn_drone, DroneSpec, Layout, LayoutFile};
+pub use mavlink::{open_mavlink, open_mavlink_tcp, open_mavlink_udp, MavMessage, MavlinkParser};
+pub use msp::{open_msp, open_msp_tcp, spawn_msp, MspVersion};
+pub use server::{listen_tcp, server_select, ActivePeer, Peer, ServerState, TcpServer};
+
+#[derive(Component)]
 pub struct Port {
     pub rx: Option<Receiver<Vec<f32>>>,
     pub last_transmition: Option<Instant>,
 }
 
+/// A device feeding its own set of drones. Lives on the same entity as its
+/// `Port`; drones point back at it through `GyroComponent::source`.
+#[derive(Component)]
+pub struct Source {
+    pub label: String,
+    pub spec: SourceSpec,
+}
+
+/// Marks the source whose `Port` follows the peer selected on `TcpServer`.
+#[derive(Component)]
+pub struct ListenSource;
+
+/// Where a source's samples come from, as given on the command line:
+/// `serial:/dev/ttyUSB0@115200`, `tcp:99.22.0.1:9922` or `listen:0.0.0.0:9922`
+/// for our own boards, `msp:/dev/ttyACM0@115200` or `msp-tcp:<addr>` for
+/// Betaflight/INAV (`msp2:`/`msp2-tcp:` to poll with MSP v2 framing), and
+/// `mavlink:<path>[@baud]`, `mavlink-udp:0.0.0.0:14550` or
+/// `mavlink-tcp:127.0.0.1:5760` for ArduPilot/PX4 vehicles and SITL.
+#[derive(Clone, Debug)]
+pub enum SourceSpec {
+    Serial {
+        path: String,
+        baudrate: u32,
+    },
+    Tcp(String),
+    Listen(String),
+    Msp {
+        path: String,
+        baudrate: u32,
+        version: MspVersion,
+    },
+    MspTcp {
+        addr: String,
+        version: MspVersion,
+    },
+    Mavlink {
+        path: String,
+        baudrate: u32,
+    },
+    MavlinkUdp(String),
+    MavlinkTcp(String),
+}
+
+impl SourceSpec {
+    /// Whether the extra channels carry the device's own attitude estimate.
+    pub fn provides_attitude(&self) -> bool {
+        matches!(
+            self,
+            Self::Msp { .. }
+                | Self::MspTcp { .. }
+                | Self::Mavlink { .. }
+                | Self::MavlinkUdp(_)
+                | Self::MavlinkTcp(_)
+        )
+    }
+}
+
+fn parse_serial(rest: &str) -> Result<(String, u32), String> {
+    match rest.rsplit_once('@') {
+        Some((path, baud)) => Ok((
+            path.to_owned(),
+            baud.parse().map_err(|_| format!("bad baudrate `{baud}`"))?,
+        )),
+        None => Ok((rest.to_owned(), 115200)),
+    }
+}
+
+impl std::str::FromStr for SourceSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("serial", rest)) => {
+                let (path, baudrate) = parse_serial(rest)?;
+                Ok(Self::Serial { path, baudrate })
+            }
+            Some(("tcp", addr)) => Ok(Self::Tcp(addr.to_owned())),
+            Some(("listen", addr)) => Ok(Self::Listen(addr.to_owned())),
+            Some((kind @ ("msp" | "msp2"), rest)) => {
+                let (path, baudrate) = parse_serial(rest)?;
+                let version = if kind == "msp" {
+                    MspVersion::V1
+                } else {
+                    MspVersion::V2
+                };
+                Ok(Self::Msp {
+                    path,
+                    baudrate,
+                    version,
+                })
+            }
+            Some((kind @ ("msp-tcp" | "msp2-tcp"), addr)) => Ok(Self::MspTcp {
+                addr: addr.to_owned(),
+                version: if kind == "msp-tcp" {
+                    MspVersion::V1
+                } else {
+                    MspVersion::V2
+                },
+            }),
+            Some(("mavlink", rest)) => {
+                let (path, baudrate) = parse_serial(rest)?;
+                Ok(Self::Mavlink { path, baudrate })
+            }
+            Some(("mavlink-udp", addr)) => Ok(Self::MavlinkUdp(addr.to_owned())),
+            Some(("mavlink-tcp", addr)) => Ok(Self::MavlinkTcp(addr.to_owned())),
+            _ => Err(format!(
+                "unknown source `{s}`, expected `serial:<path>[@baud]`, `tcp:<addr>`, \
+                 `listen:<addr>`, `msp[2]:<path>[@baud]`, `msp[2]-tcp:<addr>`, \
+                 `mavlink:<path>[@baud]`, `mavlink-udp:<addr>` or `mavlink-tcp:<addr>`"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for SourceSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msp = |version: &MspVersion| match version {
+            MspVersion::V1 => "msp",
+            MspVersion::V2 => "msp2",
+        };
+        match self {
+            Self::Serial { path, baudrate } => write!(f, "serial:{path}@{baudrate}"),
+            Self::Tcp(addr) => write!(f, "tcp:{addr}"),
+            Self::Listen(addr) => write!(f, "listen:{addr}"),
+            Self::Msp {
+                path,
+                baudrate,
+                version,
+            } => write!(f, "{}:{path}@{baudrate}", msp(version)),
+            Self::MspTcp { addr, version } => write!(f, "{}-tcp:{addr}", msp(version)),
+            Self::Mavlink { path, baudrate } => write!(f, "mavlink:{path}@{baudrate}"),
+            Self::MavlinkUdp(addr) => write!(f, "mavlink-udp:{addr}"),
+            Self::MavlinkTcp(addr) => write!(f, "mavlink-tcp:{addr}"),
+        }
+    }
+}
+
+/// Samples a reader thread may get ahead of whoever drains its channel:
+/// about a second of a 1 kHz device.
+pub(crate) const CHANNEL_CAPACITY: usize = 1024;
+
 const DELIMITER: u8 = 255;
+pub const FRAME_LEN: usize = 54;
+
+/// A decoded sample, re-emitted every time `gyro_update` consumes one from a
+/// `Port` so other systems can see the same stream. `data` has been through
+/// the source's filters, `raw` is what arrived.
+#[derive(Event, Clone)]
+pub struct GyroSample {
+    pub source: Entity,
+    pub data: Vec<f32>,
+    pub raw: Vec<f32>,
+    /// The displayed attitude of each of the source's drones right after
+    /// this sample. Several samples may arrive in a frame, the `Transform`
+    /// only holds the last of them.
+    pub attitudes: Vec<(Entity, Quat)>,
+}
 
-pub fn open(port_path: &std::path::Path, baudrate: u32) -> Receiver<Vec<f32>> {
-    let (tx, rx) = crossbeam_channel::bounded(1);
-    let mut port = serialport::new(port_path.to_string_lossy(), baudrate)
-        .timeout(std::time::Duration::from_secs(20))
-        .open_native();
-
-    while port.is_err() {
-        port = serialport::new(port_path.to_string_lossy(), baudrate)
-            .timeout(std::time::Duration::from_secs(20))
-            .open_native();
+/// Unpacks the twelve little-endian floats of a device frame and appends the
+/// timestamp field in seconds.
+pub fn decode_frame(buf: &[u8]) -> Vec<f32> {
+    let mut fbuf = buf[0..48]
+        .chunks_exact(4)
+        .map(|chuck| f32::from_le_bytes(chuck.try_into().unwrap()))
+        .collect::<Vec<f32>>();
+    let time = u32::from_le_bytes(buf[48..52].try_into().unwrap());
+    fbuf.push(time as f32 / 1.0e6);
+    fbuf
+}
+
+/// Whether `buf` ends in a frame: at least `FRAME_LEN` bytes, the last two
+/// being the trailer. The trailer may also turn up inside a payload, so
+/// anything shorter is not a frame yet.
+fn ends_in_frame(buf: &[u8]) -> bool {
+    buf.len() >= FRAME_LEN && buf[buf.len() - 2..] == [254, DELIMITER]
+}
+
+/// Every complete frame in a byte stream, such as a recording of the serial
+/// port; anything between frames is skipped.
+pub fn decode_frames(bytes: &[u8]) -> Vec<Vec<f32>> {
+    let mut frames = vec![];
+    let mut start = 0;
+    let mut end = FRAME_LEN;
+    while end <= bytes.len() {
+        if ends_in_frame(&bytes[start..end]) {
+            frames.push(decode_frame(&bytes[end - FRAME_LEN..end]));
+            start = end;
+            end += FRAME_LEN;
+        } else {
+            end += 1;
+        }
     }
+    frames
+}
+
+/// Inverse of `decode_frame`, terminated the way the serial reader expects.
+pub fn encode_frame(sample: &[f32]) -> [u8; FRAME_LEN] {
+    let mut buf = [0u8; FRAME_LEN];
+    for (chunk, value) in buf[0..48].chunks_exact_mut(4).zip(sample.iter()) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    let time = (sample.get(12).copied().unwrap_or(0.) * 1.0e6) as u32;
+    buf[48..52].copy_from_slice(&time.to_le_bytes());
+    buf[52] = 254;
+    buf[53] = DELIMITER;
+    buf
+}
+
+/// Reports why a source could not be opened and hands out a channel that is
+/// already closed, so the source shows up as such instead of taking the app
+/// down.
+pub(crate) fn open_failed(
+    what: impl std::fmt::Display,
+    e: impl std::fmt::Display,
+) -> Receiver<Vec<f32>> {
+    error!("{what}: {e}");
+    crossbeam_channel::bounded(0).1
+}
+
+/// Keeps trying to open the port until it appears, then reads frames from
+/// it until it fails.
+pub fn open(port_path: &std::path::Path, baudrate: u32) -> Receiver<Vec<f32>> {
+    open_until(port_path, baudrate, Arc::new(AtomicBool::new(false)))
+}
+
+/// `open`, giving up, port and all, once `stop` is set: while still waiting
+/// for the port to appear, or after the next read.
+pub fn open_until(
+    port_path: &std::path::Path,
+    baudrate: u32,
+    stop: Arc<AtomicBool>,
+) -> Receiver<Vec<f32>> {
+    let (tx, rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+    let name = port_path.to_string_lossy().into_owned();
 
-    let port = port.unwrap();
-    let mut reader = BufReader::new(port);
     std::thread::spawn(move || {
+        let mut warned = false;
+        let port = loop {
+            if stop.load(Ordering::Relaxed) {
+                return;
+            }
+            match serialport::new(&name, baudrate)
+                .timeout(std::time::Duration::from_secs(20))
+                .open_native()
+            {
+                Ok(port) => break port,
+                Err(e) => {
+                    if !warned {
+                        warn!("{name}: {e}, retrying");
+                        warned = true;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                }
+            }
+        };
+        info!("{name}: opened at {baudrate} baud");
+        let mut reader = BufReader::new(port);
         let mut buf = vec![];
-        loop {
+        while !stop.load(Ordering::Relaxed) {
             match reader.read_until(DELIMITER, &mut buf) {
                 Ok(_n) => {
-                    if buf[buf.len() - 2] != 254 {
-                        continue;
-                    }
-
-                    if buf.len() != 54 {
-                        buf.clear();
+                    // stopped short by a trailer inside the payload, or by
+                    // a timeout, the rest is still to come
+                    if !ends_in_frame(&buf) {
+                        // a frame still to come starts no further back
+                        if buf.len() >= FRAME_LEN {
+                            buf.drain(..buf.len() + 1 - FRAME_LEN);
+                        }
                         continue;
                     }
 
-                    let mut fbuf = buf[0..48]
-                        .chunks_exact(4)
-                        .map(|chuck| f32::from_le_bytes(chuck.try_into().unwrap()))
-                        .collect::<Vec<f32>>();
-                    let time = u32::from_le_bytes(buf[48..52].try_into().unwrap());
-
-                    if 12 == fbuf.len() {
-                        fbuf.push(time as f32 / 1.0e6);
-                        // println!("{:#?}", fbuf);
-                        tx.send(fbuf).unwrap();
-                        buf.clear();
-                    } else {
-                        // println!("last {}, {}: {:?}", buf.last().unwrap(), fbuf.len(), fbuf);
+                    // anything in front of the frame is noise
+                    if tx
+                        .send(decode_frame(&buf[buf.len() - FRAME_LEN..]))
+                        .is_err()
+                    {
+                        break;
                     }
-
                     buf.clear();
                 }
                 Err(e) => {
+                    // dropping `tx` lets the receiving side notice
                     if !matches!(e.kind(), std::io::ErrorKind::TimedOut) {
-                        exit(1);
+                        error!("{name}: {e}");
+                        break;
                     }
                 }
             }
@@ -71,39 +320,24 @@ pub fn open(port_path: &std::path::Path, baudrate: u32) -> Receiver<Vec<f32>> {
     rx
 }
 
-use std::net::TcpStream;
-pub fn open_tcp() -> Receiver<Vec<f32>> {
-    let (tx, rx) = crossbeam_channel::bounded(1);
-
-    let mut stream = TcpStream::connect("99.22.0.1:9922").unwrap();
-
-    std::thread::spawn(move || {
-        loop {
-            let mut buf = [0u8; 54];
-            stream.read_exact(&mut buf).unwrap();
-            // println!("{:?}", buf);
-            // println!("read: {:?}", );
-            // if buf[buf.len() - 2] != 254 {
-            //     continue;
-            // }
-
-            let mut fbuf = buf[0..48]
-                .chunks_exact(4)
-                .map(|chuck| f32::from_le_bytes(chuck.try_into().unwrap()))
-                .collect::<Vec<f32>>();
-            let time = u32::from_le_bytes(buf[48..52].try_into().unwrap());
-
-            if 12 == fbuf.len() {
-                // TODO
-                fbuf.push(time as f32 / 1.0e6);
-                // println!("{:#?}", fbuf);
-                tx.send(fbuf).unwrap();
-                // buf.clear();
-            } else {
-                // println!("last {}, {}: {:?}", buf.last().unwrap(), fbuf.len(), fbuf);
-            }
-
-            // buf.clear();
+use std::net::{TcpStream, ToSocketAddrs};
+pub fn open_tcp(addr: impl ToSocketAddrs + std::fmt::Display) -> Receiver<Vec<f32>> {
+    let (tx, rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+
+    let mut stream = match TcpStream::connect(&addr) {
+        Ok(stream) => stream,
+        Err(e) => return open_failed(addr, e),
+    };
+    let name = addr.to_string();
+
+    std::thread::spawn(move || loop {
+        let mut buf = [0u8; FRAME_LEN];
+        if let Err(e) = stream.read_exact(&mut buf) {
+            error!("{name}: {e}");
+            break;
+        }
+        if tx.send(decode_frame(&buf)).is_err() {
+            break;
         }
     });
 
@@ -115,6 +349,8 @@ pub struct GyroPlugin;
 #[derive(Component)]
 pub struct GyroComponent {
     pub acc_weight: f32,
+    /// Samples averaged into the gyro bias before the estimator starts.
+    pub calibration_samples: usize,
     pub state: GyroState,
     pub x: Option<f32>,
     pub y: Option<f32>,
@@ -122,13 +358,115 @@ pub struct GyroComponent {
     pub signy: f32,
     pub offset: (f32, f32, f32),
     pub variant: DroneVariant,
+    pub source: Entity,
+    /// What the estimator makes of the attitude, before the tare.
+    pub attitude: Quat,
+    /// Turns the estimated attitude into the displayed one, see `tare`.
+    pub tare: Quat,
+    /// The gyro-only estimator takes the accelerometer's tilt on the next
+    /// sample.
+    pub realign: bool,
 }
 
-#[derive(Component)]
+impl GyroComponent {
+    /// Forgets the estimated attitude; the next sample starts over from the
+    /// accelerometer, or from level for the gyro alone.
+    pub fn reset(&mut self) {
+        self.x = None;
+        self.y = None;
+        self.z = None;
+        self.signy = 1.;
+        self.attitude = Quat::IDENTITY;
+    }
+
+    /// Sends an estimator that integrates the gyro back to the attitude the
+    /// accelerometer measures, dropping the drift collected so far.
+    pub fn realign(&mut self) {
+        match self.variant {
+            DroneVariant::Gyro => self.realign = true,
+            // starts over from the accelerometer anyway
+            DroneVariant::Both => self.reset(),
+            DroneVariant::Acc | DroneVariant::Reference => {}
+        }
+    }
+
+    /// The attitude as displayed.
+    pub fn displayed(&self) -> Quat {
+        self.tare * self.attitude
+    }
+
+    /// Takes the displayed attitude as level, keeping the heading.
+    pub fn tare_level(&mut self) {
+        let displayed = self.displayed();
+        let (yaw, _, _) = displayed.to_euler(EulerRot::YXZ);
+        self.tare = Quat::from_rotation_y(yaw) * displayed.inverse() * self.tare;
+    }
+
+    /// Takes the displayed heading as zero, keeping the tilt.
+    pub fn tare_heading(&mut self) {
+        let (yaw, _, _) = self.displayed().to_euler(EulerRot::YXZ);
+        self.tare = Quat::from_rotation_y(-yaw) * self.tare;
+    }
+
+    /// Measures the gyro bias afresh from the next samples, so the device
+    /// has to be kept still meanwhile.
+    pub fn recalibrate(&mut self) {
+        self.reset();
+        self.offset = (0., 0., 0.);
+        self.state = GyroState::Calibration(vec![]);
+    }
+}
+
+/// Name shown for a drone.
+#[derive(Component, Clone)]
+pub struct DroneLabel(pub String);
+
+/// Where a drone sits when it is not being moved for display, as placed by
+/// the layout.
+#[derive(Component, Clone, Copy)]
+pub struct HomePosition(pub Vec3);
+
+/// Colour a drone's model is painted in.
+#[derive(Component, Clone, Copy)]
+pub struct DroneColor(pub Color);
+
+/// The drone that per-drone views such as the HUD show; the first one when
+/// unset or gone.
+#[derive(Resource, Default)]
+pub struct SelectedDrone(pub Option<Entity>);
+
+impl SelectedDrone {
+    pub fn get(&self, drones: impl Iterator<Item = Entity>) -> Option<Entity> {
+        chosen_or_first(self.0, drones)
+    }
+}
+
+/// `chosen` while it is still among `entities`, the first of them otherwise.
+pub fn chosen_or_first(
+    chosen: Option<Entity>,
+    mut entities: impl Iterator<Item = Entity>,
+) -> Option<Entity> {
+    let first = entities.next();
+    let chosen = chosen.filter(|e| first == Some(*e) || entities.any(|d| d == *e));
+    chosen.or(first)
+}
+
+/// Roll, pitch and yaw in degrees, as the plots, the HUD and the broadcast
+/// all show them.
+pub fn euler_degrees(rotation: Quat) -> (f32, f32, f32) {
+    let (roll, yaw, pitch) = rotation.to_euler(EulerRot::XYZ);
+    (roll.to_degrees(), pitch.to_degrees(), yaw.to_degrees())
+}
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DroneVariant {
     Gyro,
     Acc,
     Both,
+    /// Mirrors the attitude a flight controller or autopilot reports about
+    /// itself.
+    Reference,
 }
 
 pub enum GyroState {
@@ -138,250 +476,287 @@ pub enum GyroState {
 
 impl Plugin for GyroPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, gyro_spawn)
-            .add_systems(Update, gyro_update);
+        app.init_resource::<ActivePeer>()
+            .init_resource::<SelectedDrone>()
+            .init_resource::<Config>()
+            .init_resource::<Layout>()
+            .init_resource::<LayoutFile>()
+            .add_event::<GyroSample>()
+            .add_systems(Startup, gyro_spawn)
+            .add_systems(Update, layout_ui)
+            .add_systems(
+                Update,
+                (
+                    server_select.run_if(resource_exists::<TcpServer>()),
+                    gyro_update,
+                )
+                    .chain(),
+            );
     }
 }
 
-// const INIT_ACC_WEIGHT: f32 = 0.;
-const INIT_ACC_WEIGHT: f32 = 0.08;
-// const INIT_ACC_WEIGHT: f32 = 1.;
-
-pub fn gyro_spawn(
-    mut coms: Commands,
-    asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+pub fn gyro_update(
+    mut ports: Query<(
+        Entity,
+        &mut Port,
+        Option<&Source>,
+        Option<&mut SourceFilters>,
+    )>,
+    mut samples: EventWriter<GyroSample>,
+    mut query: Query<(Entity, &mut Transform, &mut GyroComponent)>,
 ) {
-    coms.spawn((
-        PbrBundle {
-            mesh: asset_server.load("Drone2.obj"),
-            material: materials.add(StandardMaterial {
-                base_color: Color::RED,
-                ..Default::default()
-            }),
-            transform: Transform::from_scale([1.; 3].into()),
-            ..default()
-        },
-        GyroComponent {
-            acc_weight: INIT_ACC_WEIGHT,
-            state: GyroState::Calibration(vec![]),
-            x: None,
-            y: None,
-            z: None,
-            signy: 1.0,
-            offset: (0.0, 0.0, 0.0),
-            variant: DroneVariant::Both,
-        },
-    ));
-    coms.spawn((
-        PbrBundle {
-            mesh: asset_server.load("Drone2.obj"),
-            material: materials.add(StandardMaterial {
-                base_color: Color::BLUE,
-                ..Default::default()
-            }),
-            // transform: Transform::from_scale([1.; 3].into()),
-            transform: Transform::from_xyz(7., 0., 0.).with_scale([1.; 3].into()),
-            ..default()
-        },
-        GyroComponent {
-            acc_weight: INIT_ACC_WEIGHT,
-            state: GyroState::Calibration(vec![]),
-            x: None,
-            y: None,
-            z: None,
-            signy: 1.0,
-            offset: (0.0, 0.0, 0.0),
-            variant: DroneVariant::Gyro,
-        },
-    ));
-    coms.spawn((
-        PbrBundle {
-            mesh: asset_server.load("Drone2.obj"),
-            material: materials.add(StandardMaterial {
-                base_color: Color::YELLOW,
-                ..Default::default()
-            }),
-            // transform: Transform::from_scale([1.; 3].into()),
-            transform: Transform::from_xyz(-7., 0., 0.).with_scale([1.; 3].into()),
-            ..default()
-        },
-        GyroComponent {
-            acc_weight: INIT_ACC_WEIGHT,
-            state: GyroState::Calibration(vec![]),
-            x: None,
-            y: None,
-            z: None,
-            signy: 1.0,
-            offset: (0.0, 0.0, 0.0),
-            variant: DroneVariant::Acc,
-        },
-    ));
+    for (source, mut port, label, mut filters) in ports.iter_mut() {
+        let Some(p) = port.rx.clone() else {
+            continue;
+        };
+        loop {
+            let raw = match p.try_recv() {
+                Ok(raw) => raw,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // the reader has said why, if it knew
+                    let label = label.map_or("source", |s| &s.label);
+                    warn!("{label}: no more samples");
+                    port.rx = None;
+                    break;
+                }
+            };
+            let now = Instant::now();
+            let mut v = raw.clone();
+            if let Some(filters) = filters.as_mut() {
+                filters.apply(&mut v);
+            }
+            let mut attitudes = vec![];
+            for (drone, mut telo, mut gyro) in query.iter_mut() {
+                if gyro.source == source {
+                    let mut estimate = Transform::from_rotation(gyro.attitude);
+                    gyro_apply(&mut estimate, &mut gyro, &v);
+                    gyro.attitude = estimate.rotation;
+                    telo.rotation = gyro.displayed();
+                    attitudes.push((drone, telo.rotation));
+                }
+            }
+
+            port.last_transmition = Some(now);
+            samples.send(GyroSample {
+                source,
+                data: v,
+                raw,
+                attitudes,
+            });
+        }
+    }
+}
+
+/// The accelerometer reading of a sample in model axes, as the estimators
+/// use it: level and at rest it points up.
+pub fn acc_to_model(v: &[f32]) -> Vec3 {
+    Vec3::new(-v[3], v[5], -v[4])
+}
+
+/// Roll and pitch, radians, of the accelerometer reading taken as pointing
+/// up.
+fn acc_tilt(v: &[f32]) -> (f32, f32) {
+    let Vec3 {
+        x: rx,
+        y: ry,
+        z: rz,
+    } = acc_to_model(v);
+
+    let roll = f32::atan2(rz, (rx * rx + ry * ry).sqrt());
+    // let roll = 0.0;
+    let pitch = f32::atan2(-rx, (ry * ry + rz * rz).sqrt());
+
+    // let roll = -rz.atan2(ry);
+    // let pitch = -rx.atan2(ry);
+    (roll, pitch)
 }
 
-pub fn gyro_update(mut port: ResMut<Port>, mut query: Query<(&mut Transform, &mut GyroComponent)>) {
-    // let last_transmit = port.last_transmition;
-    if let Some(p) = port.rx.clone() {
-        match p.try_recv() {
-            Ok(v) => {
-                let now = Instant::now();
-                // println!("{:#?}", v);
-                // let (mut telo, mut gyro) = query.iter_mut().next().unwrap();
-                for (mut telo, mut gyro) in query.iter_mut() {
-                    match &mut gyro.state {
-                        GyroState::Calibration(cal_v) => {
-                            cal_v.push((v[0], v[1], v[2]));
-                            if cal_v.len() > 100 {
-                                let mean_x =
-                                    cal_v.iter().map(|x| x.0).sum::<f32>() / cal_v.len() as f32;
-                                let mean_y =
-                                    cal_v.iter().map(|x| x.1).sum::<f32>() / cal_v.len() as f32;
-                                let mean_z =
-                                    cal_v.iter().map(|x| x.2).sum::<f32>() / cal_v.len() as f32;
-                                gyro.offset = (mean_x, mean_y, mean_z);
-                                gyro.state = GyroState::Active;
+fn gyro_apply(telo: &mut Transform, gyro: &mut GyroComponent, v: &[f32]) {
+    if let DroneVariant::Reference = gyro.variant {
+        // already fused on the device, nothing to calibrate
+        let (roll, pitch, yaw) = (v[9].to_radians(), v[10].to_radians(), v[11].to_radians());
+        telo.rotation =
+            Quat::from_rotation_y(-yaw) * Quat::from_euler(EulerRot::XYZ, roll, 0.0, pitch);
+        gyro.x = Some(roll);
+        gyro.y = Some(yaw);
+        gyro.z = Some(pitch);
+        return;
+    }
+
+    match &mut gyro.state {
+        GyroState::Calibration(cal_v) => {
+            cal_v.push((v[0], v[1], v[2]));
+            if cal_v.len() > gyro.calibration_samples {
+                let mean_x = cal_v.iter().map(|x| x.0).sum::<f32>() / cal_v.len() as f32;
+                let mean_y = cal_v.iter().map(|x| x.1).sum::<f32>() / cal_v.len() as f32;
+                let mean_z = cal_v.iter().map(|x| x.2).sum::<f32>() / cal_v.len() as f32;
+                gyro.offset = (mean_x, mean_y, mean_z);
+                gyro.state = GyroState::Active;
+                debug!(source = ?gyro.source, bias = ?gyro.offset, "calibrated");
+            }
+        }
+        GyroState::Active => {
+            match gyro.variant {
+                DroneVariant::Reference => unreachable!("handled before calibration"),
+                DroneVariant::Gyro => {
+                    let gx = (v[0] - gyro.offset.0) * v[12] * PI / 180.;
+                    let gz = (v[1] - gyro.offset.1) * v[12] * PI / 180.;
+
+                    if gyro.realign {
+                        let (roll, pitch) = acc_tilt(v);
+                        telo.rotation = Quat::from_euler(EulerRot::XYZ, roll, 0.0, pitch);
+                        gyro.x = Some(roll);
+                        gyro.y = Some(0.);
+                        gyro.z = Some(pitch);
+                        gyro.realign = false;
+                    } else if gyro.x.is_some() {
+                        let prevx = gyro.x.unwrap();
+                        let prevz = gyro.z.unwrap();
+
+                        let gyrox = prevx + gx;
+                        let gyroz = prevz + gz;
+
+                        let xr = gyrox;
+                        let zr = gyroz;
+
+                        trace!(
+                            source = ?gyro.source,
+                            x = gyrox.to_degrees(),
+                            z = gyroz.to_degrees(),
+                            "gyro only"
+                        );
+                        telo.rotation = Quat::from_euler(EulerRot::XYZ, xr, 0.0, zr);
+                        gyro.x = Some(xr);
+                        gyro.z = Some(zr);
+                    } else {
+                        gyro.x = Some(0.);
+                        gyro.y = Some(0.);
+                        gyro.z = Some(0.);
+                    }
+                }
+                DroneVariant::Acc => {
+                    let (roll, pitch) = acc_tilt(v);
+
+                    trace!(
+                        source = ?gyro.source,
+                        roll = roll.to_degrees(),
+                        pitch = pitch.to_degrees(),
+                        "acc only"
+                    );
+
+                    telo.rotation = Quat::from_euler(EulerRot::XYZ, roll, 0.0, pitch);
+                }
+                DroneVariant::Both => {
+                    let gx = (v[0] - gyro.offset.0) * v[12] * PI / 180.;
+                    let gz = (v[1] - gyro.offset.1) * v[12] * PI / 180.;
+
+                    // row acc data
+                    let Vec3 {
+                        x: rx,
+                        y: ry,
+                        z: rz,
+                    } = acc_to_model(v);
+
+                    let signy = ry.signum();
+
+                    let roll = f32::atan2(rz, signy * (rx * rx + ry * ry).sqrt());
+                    let pitch = f32::atan2(-rx, signy * (ry * ry + rz * rz).sqrt());
+
+                    if gyro.x.is_some() {
+                        let aw = gyro.acc_weight;
+
+                        let prevx = gyro.x.unwrap();
+                        let prevz = gyro.z.unwrap();
+
+                        let gyrox = (prevx + gx) % 360.;
+                        let gyroz = (prevz + gz) % 360.;
+
+                        let xr = if signy < 0. {
+                            gyrox
+                        } else {
+                            if gyro.signy < 0. {
+                                roll
+                            } else {
+                                roll * aw + gyrox * (1. - aw)
                             }
-                        }
-                        GyroState::Active => {
-                            match gyro.variant {
-                                DroneVariant::Gyro => {
-                                    let gx = (v[0] - gyro.offset.0) * v[12] * PI / 180.;
-                                    let gz = (v[1] - gyro.offset.1) * v[12] * PI / 180.;
-
-                                    if gyro.x.is_some() {
-                                        let prevx = gyro.x.unwrap();
-                                        let prevz = gyro.z.unwrap();
-
-                                        let gyrox = prevx + gx;
-                                        let gyroz = prevz + gz;
-
-                                        let xr = gyrox;
-                                        let zr = gyroz;
-
-                                        // println!("gyro x: {gyrox}\ngyroz: {gyroz}\n\nroll: {roll}\npitch: {pitch}\n\nestimated x: {xr}\nestimated z: {zr}\n\n\n");
-                                        println!(
-                                            "only gyrox: {}\nonly gyroz: {}",
-                                            gyrox / PI * 180.0,
-                                            gyroz / PI * 180.0
-                                        );
-                                        telo.rotation =
-                                            Quat::from_euler(EulerRot::XYZ, xr, 0.0, zr);
-                                        gyro.x = Some(xr);
-                                        gyro.z = Some(zr);
-                                    } else {
-                                        gyro.x = Some(0.);
-                                        gyro.y = Some(0.);
-                                        gyro.z = Some(0.);
-                                    }
-                                }
-                                DroneVariant::Acc => {
-                                    // raw acc data
-                                    let rx = -v[3];
-                                    let ry = v[5];
-                                    let rz = -v[4];
-
-                                    let roll = f32::atan2(rz, (rx * rx + ry * ry).sqrt());
-                                    // let roll = 0.0;
-                                    let pitch = f32::atan2(-rx, (ry * ry + rz * rz).sqrt());
-
-                                    // let roll = -rz.atan2(ry);
-                                    // let pitch = -rx.atan2(ry);
-
-                                    println!(
-                                        "only roll: {}\nonly pitch: {}\n\n",
-                                        roll / PI * 180.,
-                                        pitch / PI * 180.
-                                    );
-
-                                    telo.rotation =
-                                        Quat::from_euler(EulerRot::XYZ, roll, 0.0, pitch);
-                                }
-                                DroneVariant::Both => {
-                                    let gx = (v[0] - gyro.offset.0) * v[12] * PI / 180.;
-                                    let gz = (v[1] - gyro.offset.1) * v[12] * PI / 180.;
-
-                                    // row acc data
-                                    let rx = -v[3];
-                                    let ry = v[5];
-                                    let rz = -v[4];
-
-                                    let signy = ry.signum();
-
-                                    let roll = f32::atan2(rz, signy * (rx * rx + ry * ry).sqrt());
-                                    let pitch = f32::atan2(-rx, signy * (ry * ry + rz * rz).sqrt());
-
-                                    if gyro.x.is_some() {
-                                        let aw = gyro.acc_weight;
-
-                                        let prevx = gyro.x.unwrap();
-                                        let prevz = gyro.z.unwrap();
-
-                                        let gyrox = (prevx + gx) % 360.;
-                                        let gyroz = (prevz + gz) % 360.;
-
-                                        let xr = if signy < 0. {
-                                            gyrox
-                                        } else {
-                                            if gyro.signy < 0. {
-                                                roll
-                                            } else {
-                                                roll * aw + gyrox * (1. - aw)
-                                            }
-                                        };
-
-                                        let zr = if signy < 0. {
-                                            gyroz
-                                        } else {
-                                            if gyro.signy < 0. {
-                                                pitch
-                                            } else {
-                                                pitch * aw + gyroz * (1. - aw)
-                                            }
-                                        };
-
-                                        println!("sign: {}\n\ngyroxt: {}\ngyrozt: {}\n\nroll: {}\npitch: {}\n\nestimated x: {}\nestimated z: {}\n\n\n",
-                                                signy,
-                                                gyrox / PI * 180.,
-                                                gyroz / PI * 180.,
-                                                roll / PI * 180.,
-                                                pitch / PI * 180.,
-                                                xr / PI * 180.,
-                                                zr / PI * 180.,
-                                            );
-
-                                        telo.rotation = Quat::IDENTITY;
-                                        telo.rotate_local_x(xr);
-                                        telo.rotate_local_z(zr);
-
-                                        // if signy < 0.0 {
-                                        //     telo.rotate_local_z(-PI);
-                                        // }
-
-                                        // telo.rotation =
-                                        //     Quat::from_euler(EulerRot::XYZ, xr, 0.0, zr).to_euler(EulerRot::XYZ);
-
-                                        // let rotation_this_frame =
-                                        //     Quat::from_axis_angle(Vec3::X, xr)
-                                        //         * Quat::from_axis_angle(Vec3::Z, zr);
-                                        // telo.rotation = Quat::NAN * rotation_this_frame;
-
-                                        gyro.signy = signy;
-                                        gyro.x = Some(xr);
-                                        gyro.z = Some(zr);
-                                    } else {
-                                        gyro.x = Some(roll);
-                                        gyro.y = Some(0.);
-                                        gyro.z = Some(pitch);
-                                    }
-                                }
+                        };
+
+                        let zr = if signy < 0. {
+                            gyroz
+                        } else {
+                            if gyro.signy < 0. {
+                                pitch
+                            } else {
+                                pitch * aw + gyroz * (1. - aw)
                             }
-                        }
+                        };
+
+                        trace!(
+                            source = ?gyro.source,
+                            sign = signy,
+                            gyro_x = gyrox.to_degrees(),
+                            gyro_z = gyroz.to_degrees(),
+                            roll = roll.to_degrees(),
+                            pitch = pitch.to_degrees(),
+                            x = xr.to_degrees(),
+                            z = zr.to_degrees(),
+                            "fused"
+                        );
+
+                        telo.rotation = Quat::IDENTITY;
+                        telo.rotate_local_x(xr);
+                        telo.rotate_local_z(zr);
+
+                        // if signy < 0.0 {
+                        //     telo.rotate_local_z(-PI);
+                        // }
+
+                        // telo.rotation =
+                        //     Quat::from_euler(EulerRot::XYZ, xr, 0.0, zr).to_euler(EulerRot::XYZ);
+
+                        // let rotation_this_frame =
+                        //     Quat::from_axis_angle(Vec3::X, xr)
+                        //         * Quat::from_axis_angle(Vec3::Z, zr);
+                        // telo.rotation = Quat::NAN * rotation_this_frame;
+
+                        gyro.signy = signy;
+                        gyro.x = Some(xr);
+                        gyro.z = Some(zr);
+                    } else {
+                        gyro.x = Some(roll);
+                        gyro.y = Some(0.);
+                        gyro.z = Some(pitch);
                     }
                 }
-
-                port.last_transmition = Some(now);
             }
-            Err(_) => {}
-        };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(first: f32) -> Vec<f32> {
+        let mut sample = vec![first, 0.5, -1., 0., 0., 9.81, 0., 0., 0., 0., 0., 0.];
+        sample.push(0.25);
+        sample
+    }
+
+    #[test]
+    fn frames_round_trip_past_noise() {
+        let mut bytes = vec![1, 2, 254, DELIMITER, 3];
+        bytes.extend_from_slice(&encode_frame(&sample(1.)));
+        bytes.extend_from_slice(&encode_frame(&sample(2.)));
+        assert_eq!(decode_frames(&bytes), vec![sample(1.), sample(2.)]);
+    }
+
+    #[test]
+    fn trailer_inside_payload_keeps_frame() {
+        let tricky = f32::from_le_bytes([254, DELIMITER, 0, 0]);
+        let mut bytes = encode_frame(&sample(tricky)).to_vec();
+        bytes.extend_from_slice(&encode_frame(&sample(2.)));
+        assert_eq!(decode_frames(&bytes), vec![sample(tricky), sample(2.)]);
     }
 }
@@ -0,0 +1,413 @@
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use bevy::log::{error, warn};
+use crossbeam_channel::Receiver;
+
+use super::{open_failed, CHANNEL_CAPACITY};
+
+pub const MSP_RAW_IMU: u16 = 102;
+pub const MSP_ATTITUDE: u16 = 108;
+
+/// Betaflight and INAV both report `MSP_RAW_IMU` accelerations with 1 g
+/// nominally at 512 LSB.
+const ACC_1G: f32 = 512.;
+/// Pause before asking again after the flight controller refused a command.
+const REJECTED_RETRY: Duration = Duration::from_millis(250);
+
+/// Framing used for requests. Responses of either version are accepted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MspVersion {
+    V1,
+    V2,
+}
+
+fn crc8_dvb_s2(mut crc: u8, byte: u8) -> u8 {
+    crc ^= byte;
+    for _ in 0..8 {
+        crc = if crc & 0x80 != 0 {
+            (crc << 1) ^ 0xD5
+        } else {
+            crc << 1
+        };
+    }
+    crc
+}
+
+pub fn encode_request(version: MspVersion, cmd: u16, payload: &[u8]) -> Vec<u8> {
+    match version {
+        MspVersion::V1 => {
+            let mut frame = vec![b'$', b'M', b'<', payload.len() as u8, cmd as u8];
+            frame.extend_from_slice(payload);
+            let checksum = frame[3..].iter().fold(0, |acc, b| acc ^ b);
+            frame.push(checksum);
+            frame
+        }
+        MspVersion::V2 => {
+            let mut frame = vec![b'$', b'X', b'<', 0];
+            frame.extend_from_slice(&cmd.to_le_bytes());
+            frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+            frame.extend_from_slice(payload);
+            let crc = frame[3..].iter().fold(0, |crc, b| crc8_dvb_s2(crc, *b));
+            frame.push(crc);
+            frame
+        }
+    }
+}
+
+fn read_u8(r: &mut impl Read) -> std::io::Result<u8> {
+    let mut b = [0u8];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Reads the next response frame, skipping any noise in front of it.
+pub fn read_response(r: &mut impl Read) -> std::io::Result<(u16, Vec<u8>)> {
+    while read_u8(r)? != b'$' {}
+    let version = read_u8(r)?;
+    let direction = read_u8(r)?;
+
+    let (cmd, payload) = match version {
+        b'M' => {
+            let len = read_u8(r)?;
+            let cmd = read_u8(r)?;
+            let mut payload = vec![0u8; len as usize];
+            r.read_exact(&mut payload)?;
+            let checksum = payload.iter().fold(len ^ cmd, |acc, b| acc ^ b);
+            if read_u8(r)? != checksum {
+                return Err(invalid("MSP v1 checksum mismatch"));
+            }
+            (cmd as u16, payload)
+        }
+        b'X' => {
+            let mut header = [0u8; 5];
+            r.read_exact(&mut header)?;
+            let cmd = u16::from_le_bytes([header[1], header[2]]);
+            let len = u16::from_le_bytes([header[3], header[4]]);
+            let mut payload = vec![0u8; len as usize];
+            r.read_exact(&mut payload)?;
+            let crc = header
+                .iter()
+                .chain(payload.iter())
+                .fold(0, |crc, b| crc8_dvb_s2(crc, *b));
+            if read_u8(r)? != crc {
+                return Err(invalid("MSP v2 checksum mismatch"));
+            }
+            (cmd, payload)
+        }
+        _ => return Err(invalid("not an MSP frame")),
+    };
+
+    match direction {
+        b'>' => Ok((cmd, payload)),
+        b'!' => Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!("flight controller rejected MSP command {cmd}"),
+        )),
+        _ => Err(invalid("unexpected MSP direction")),
+    }
+}
+
+fn request<S: Read + Write>(
+    stream: &mut BufReader<S>,
+    version: MspVersion,
+    cmd: u16,
+) -> std::io::Result<Vec<u8>> {
+    stream
+        .get_mut()
+        .write_all(&encode_request(version, cmd, &[]))?;
+    loop {
+        match read_response(stream) {
+            Ok((c, payload)) if c == cmd => return Ok(payload),
+            // stale answer to an earlier, timed out request
+            Ok(_) => continue,
+            Err(e) if e.kind() == ErrorKind::InvalidData => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn i16s(payload: &[u8]) -> impl Iterator<Item = f32> + '_ {
+    payload
+        .chunks_exact(2)
+        .map(|c| i16::from_le_bytes([c[0], c[1]]) as f32)
+}
+
+/// The sample made of an `MSP_RAW_IMU` and an `MSP_ATTITUDE` payload, `None`
+/// if either is too short.
+fn to_sample(imu: &[u8], att: &[u8], dt: f32) -> Option<Vec<f32>> {
+    if imu.len() < 18 || att.len() < 6 {
+        return None;
+    }
+    let raw = i16s(imu).collect::<Vec<_>>();
+    let att = i16s(att).collect::<Vec<_>>();
+    let mut sample = Vec::with_capacity(13);
+    sample.extend_from_slice(&raw[3..6]);
+    sample.extend(raw[0..3].iter().map(|a| a / ACC_1G));
+    sample.extend_from_slice(&raw[6..9]);
+    sample.extend([att[0] / 10., att[1] / 10., att[2]]);
+    sample.push(dt);
+    Some(sample)
+}
+
+/// Polls `MSP_RAW_IMU` and `MSP_ATTITUDE` as fast as the flight controller
+/// answers and hands out the same 13-float samples the device frames decode
+/// to: gyro (deg/s), acc (g), raw mag, the FC's own roll/pitch/yaw (deg) in
+/// the extra channels, and the time since the previous sample.
+pub fn spawn_msp<S: Read + Write + Send + 'static>(
+    name: String,
+    stream: S,
+    version: MspVersion,
+) -> Receiver<Vec<f32>> {
+    let (tx, rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+
+    std::thread::spawn(move || {
+        let mut stream = BufReader::new(stream);
+        let mut last = None;
+        let mut rejected = false;
+        loop {
+            let polled = request(&mut stream, version, MSP_RAW_IMU)
+                .and_then(|imu| Ok((imu, request(&mut stream, version, MSP_ATTITUDE)?)));
+            let (imu, att) = match polled {
+                Ok(frames) => frames,
+                // a socket's read timeout shows up as `WouldBlock` on Unix
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                    continue
+                }
+                // e.g. while the FC is still booting, so it is asked again
+                Err(e) if e.kind() == ErrorKind::Unsupported => {
+                    if !rejected {
+                        warn!("{name}: {e}, still polling");
+                        rejected = true;
+                    }
+                    std::thread::sleep(REJECTED_RETRY);
+                    continue;
+                }
+                Err(e) => {
+                    error!("{name}: {e}");
+                    break;
+                }
+            };
+            rejected = false;
+
+            let now = Instant::now();
+            let dt = last.map_or(0., |l: Instant| (now - l).as_secs_f32());
+            let Some(sample) = to_sample(&imu, &att, dt) else {
+                continue;
+            };
+            last = Some(now);
+
+            if tx.send(sample).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+pub fn open_msp(
+    port_path: &std::path::Path,
+    baudrate: u32,
+    version: MspVersion,
+) -> Receiver<Vec<f32>> {
+    let name = port_path.to_string_lossy().into_owned();
+    match serialport::new(&name, baudrate)
+        .timeout(Duration::from_millis(500))
+        .open()
+    {
+        Ok(port) => spawn_msp(name, port, version),
+        Err(e) => open_failed(name, e),
+    }
+}
+
+pub fn open_msp_tcp(
+    addr: impl std::net::ToSocketAddrs + std::fmt::Display,
+    version: MspVersion,
+) -> Receiver<Vec<f32>> {
+    let stream = std::net::TcpStream::connect(&addr).and_then(|s| {
+        s.set_read_timeout(Some(Duration::from_millis(500)))
+            .map(|_| s)
+    });
+    match stream {
+        Ok(stream) => spawn_msp(addr.to_string(), stream, version),
+        Err(e) => open_failed(addr, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A response as the flight controller would send it: the request
+    /// framing with the direction swapped, which the checksum leaves out.
+    fn response(version: MspVersion, direction: u8, cmd: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = encode_request(version, cmd, payload);
+        frame[2] = direction;
+        frame
+    }
+
+    fn i16_payload(values: &[i16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// Answers from a script and swallows the requests.
+    struct Scripted(Cursor<Vec<u8>>);
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    /// A script that first times out the way a TCP socket does on Unix.
+    struct Stalling {
+        stalled: bool,
+        script: Scripted,
+    }
+
+    impl Read for Stalling {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if !self.stalled {
+                self.stalled = true;
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            self.script.read(buf)
+        }
+    }
+
+    impl Write for Stalling {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.script.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.script.flush()
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn crc8_dvb_s2_check_value() {
+        let crc = b"123456789".iter().fold(0, |crc, b| crc8_dvb_s2(crc, *b));
+        assert_eq!(crc, 0xBC);
+    }
+
+    #[test]
+    fn encodes_v1_request() {
+        assert_eq!(
+            encode_request(MspVersion::V1, MSP_RAW_IMU, &[]),
+            [b'$', b'M', b'<', 0, 102, 102]
+        );
+    }
+
+    #[test]
+    fn encodes_v2_request() {
+        let frame = encode_request(MspVersion::V2, MSP_ATTITUDE, &[]);
+        assert_eq!(&frame[..8], [b'$', b'X', b'<', 0, 108, 0, 0, 0]);
+        let crc = frame[3..8].iter().fold(0, |crc, b| crc8_dvb_s2(crc, *b));
+        assert_eq!(frame[8], crc);
+    }
+
+    #[test]
+    fn round_trips_both_versions() {
+        let payload = [1, 2, 3, 250, 251];
+        for version in [MspVersion::V1, MspVersion::V2] {
+            let frame = response(version, b'>', MSP_RAW_IMU, &payload);
+            let decoded = read_response(&mut Cursor::new(frame)).unwrap();
+            assert_eq!(decoded, (MSP_RAW_IMU, payload.to_vec()));
+        }
+    }
+
+    #[test]
+    fn skips_noise_before_a_frame() {
+        let mut bytes = vec![0, 0xFF, b'M', b'<'];
+        bytes.extend(response(MspVersion::V2, b'>', MSP_ATTITUDE, &[7, 0]));
+        let decoded = read_response(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(decoded, (MSP_ATTITUDE, vec![7, 0]));
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        for version in [MspVersion::V1, MspVersion::V2] {
+            let mut frame = response(version, b'>', MSP_RAW_IMU, &[1, 2]);
+            *frame.last_mut().unwrap() ^= 0x55;
+            let err = read_response(&mut Cursor::new(frame)).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn reports_error_responses() {
+        for version in [MspVersion::V1, MspVersion::V2] {
+            let frame = response(version, b'!', MSP_RAW_IMU, &[]);
+            let err = read_response(&mut Cursor::new(frame)).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+        }
+    }
+
+    #[test]
+    fn scales_to_device_units() {
+        // acc, gyro, mag as the FC sends them
+        let imu = i16_payload(&[512, -256, 1024, 10, -20, 30, 4, 5, 6]);
+        // roll and pitch in tenths of a degree, yaw in degrees
+        let att = i16_payload(&[-125, 300, 270]);
+        let sample = to_sample(&imu, &att, 0.02).unwrap();
+        assert_eq!(
+            sample,
+            [10., -20., 30., 1., -0.5, 2., 4., 5., 6., -12.5, 30., 270., 0.02]
+        );
+        assert!(to_sample(&imu[..16], &att, 0.).is_none());
+    }
+
+    #[test]
+    fn keeps_polling_after_an_error_response() {
+        let imu = i16_payload(&[0, 0, 512, 1, 2, 3, 0, 0, 0]);
+        let att = i16_payload(&[10, 20, 30]);
+        let mut script = response(MspVersion::V1, b'!', MSP_RAW_IMU, &[]);
+        script.extend(response(MspVersion::V1, b'>', MSP_RAW_IMU, &imu));
+        script.extend(response(MspVersion::V1, b'>', MSP_ATTITUDE, &att));
+
+        let rx = spawn_msp(
+            "test".to_owned(),
+            Scripted(Cursor::new(script)),
+            MspVersion::V1,
+        );
+        // the thread ends once the script runs out
+        let samples = rx.iter().collect::<Vec<_>>();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(&samples[0][..6], [1., 2., 3., 0., 0., 1.]);
+    }
+
+    #[test]
+    fn keeps_polling_after_a_read_timeout() {
+        let imu = i16_payload(&[0, 0, 512, 1, 2, 3, 0, 0, 0]);
+        let att = i16_payload(&[10, 20, 30]);
+        let mut script = response(MspVersion::V2, b'>', MSP_RAW_IMU, &imu);
+        script.extend(response(MspVersion::V2, b'>', MSP_ATTITUDE, &att));
+
+        let stream = Stalling {
+            stalled: false,
+            script: Scripted(Cursor::new(script)),
+        };
+        let rx = spawn_msp("test".to_owned(), stream, MspVersion::V2);
+        let samples = rx.iter().collect::<Vec<_>>();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(&samples[0][9..12], [1., 2., 30.]);
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::Read;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, SendTimeoutError};
+
+use super::{decode_frame, ListenSource, Port, CHANNEL_CAPACITY, FRAME_LEN};
+
+/// A device that dialed in to the listening socket.
+///
+/// Peers are identified by their index in `ServerState::peers`. A board that
+/// drops its link and dials back in from a new source port takes over a
+/// disconnected entry of the same IP address (and its selection), while
+/// boards connected at the same time from one host, such as several SITL
+/// instances or devices behind NAT, each get their own.
+pub struct Peer {
+    pub addr: SocketAddr,
+    pub rx: Receiver<Vec<f32>>,
+    pub connected: bool,
+    pub last_sample: Option<Instant>,
+    /// Bumped every time the peer reconnects, so the active `Port` knows to
+    /// pick up the new receiver.
+    pub generation: u32,
+}
+
+#[derive(Default)]
+pub struct ServerState {
+    pub peers: Vec<Peer>,
+    /// Index into `peers`.
+    pub selected: Option<usize>,
+}
+
+#[derive(Resource, Clone)]
+pub struct TcpServer {
+    pub local_addr: SocketAddr,
+    pub state: Arc<Mutex<ServerState>>,
+}
+
+/// Which peer generation is currently wired into the listening source's `Port`.
+#[derive(Resource, Default)]
+pub struct ActivePeer(pub Option<(usize, u32)>);
+
+pub fn listen_tcp(addr: impl ToSocketAddrs) -> std::io::Result<TcpServer> {
+    let listener = TcpListener::bind(addr)?;
+    let server = TcpServer {
+        local_addr: listener.local_addr()?,
+        state: Arc::new(Mutex::new(ServerState::default())),
+    };
+
+    let state = server.state.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let Ok(addr) = stream.peer_addr() else {
+                continue;
+            };
+            info!("{addr} connected");
+            accept_peer(&state, stream, addr);
+        }
+    });
+
+    Ok(server)
+}
+
+fn accept_peer(state: &Arc<Mutex<ServerState>>, stream: TcpStream, addr: SocketAddr) {
+    let (tx, rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+
+    let mut guard = state.lock().unwrap();
+    let returning = guard
+        .peers
+        .iter()
+        .position(|p| !p.connected && p.addr.ip() == addr.ip());
+    let (index, generation) = match returning {
+        Some(index) => {
+            let peer = &mut guard.peers[index];
+            info!("{addr} reconnected, was {}", peer.addr);
+            peer.addr = addr;
+            peer.rx = rx;
+            peer.connected = true;
+            peer.generation += 1;
+            (index, peer.generation)
+        }
+        None => {
+            guard.peers.push(Peer {
+                addr,
+                rx,
+                connected: true,
+                last_sample: None,
+                generation: 0,
+            });
+            (guard.peers.len() - 1, 0)
+        }
+    };
+    if guard.selected.is_none() {
+        guard.selected = Some(index);
+    }
+    drop(guard);
+
+    let state = state.clone();
+    std::thread::spawn(move || {
+        let mut stream = stream;
+        loop {
+            let mut buf = [0u8; FRAME_LEN];
+            if let Err(e) = stream.read_exact(&mut buf) {
+                info!("{addr} disconnected: {e}");
+                break;
+            }
+
+            let selected = {
+                let mut guard = state.lock().unwrap();
+                let peer = &mut guard.peers[index];
+                if peer.generation != generation {
+                    // only entries of ended connections are taken over
+                    warn!("{addr}: superseded by {}", peer.addr);
+                    return;
+                }
+                peer.last_sample = Some(Instant::now());
+                guard.selected == Some(index)
+            };
+
+            // what the others send is dropped, or it would be replayed as
+            // a stale backlog once they get selected
+            if !selected {
+                continue;
+            }
+            // not blocking for long: the user can switch away from the peer
+            // while it waits on a full channel
+            let frame = decode_frame(&buf);
+            if let Err(SendTimeoutError::Disconnected(_)) =
+                tx.send_timeout(frame, Duration::from_millis(100))
+            {
+                break;
+            }
+        }
+
+        let mut guard = state.lock().unwrap();
+        let peer = &mut guard.peers[index];
+        if peer.generation == generation {
+            peer.connected = false;
+        }
+    });
+}
+
+/// Points the listening source at the selected peer, following it across
+/// reconnects.
+pub fn server_select(
+    server: Res<TcpServer>,
+    mut active: ResMut<ActivePeer>,
+    mut ports: Query<&mut Port, With<ListenSource>>,
+) {
+    let Ok(mut port) = ports.get_single_mut() else {
+        return;
+    };
+    let guard = server.state.lock().unwrap();
+    let Some(index) = guard.selected else {
+        return;
+    };
+    let Some(peer) = guard.peers.get(index) else {
+        return;
+    };
+    if active.0 != Some((index, peer.generation)) {
+        active.0 = Some((index, peer.generation));
+        // sent while the previous peer was still selected
+        for _ in peer.rx.try_iter() {}
+        port.rx = Some(peer.rx.clone());
+        port.last_transmition = None;
+    }
+}
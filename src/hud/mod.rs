@@ -0,0 +1,210 @@
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Align2, Color32, FontId, Pos2, Rect, Shape, Stroke, Vec2};
+use bevy_egui::EguiContexts;
+
+use crate::gyro::{euler_degrees, DroneLabel, SelectedDrone};
+
+const SKY: Color32 = Color32::from_rgb(40, 110, 190);
+const GROUND: Color32 = Color32::from_rgb(130, 85, 40);
+const MARKING: Color32 = Color32::WHITE;
+const SYMBOL: Color32 = Color32::YELLOW;
+
+/// Side of the square attitude indicator, in points.
+const SIZE: f32 = 240.;
+/// Screen points per degree of pitch on the ladder.
+const PITCH_SCALE: f32 = 3.;
+/// Screen points per degree on the heading tape.
+const HEADING_SCALE: f32 = 3.;
+const TAPE_HEIGHT: f32 = 34.;
+
+/// Roll scale ticks, degrees either side of wings level.
+const ROLL_TICKS: [f32; 6] = [0., 10., 20., 30., 45., 60.];
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, hud_ui);
+    }
+}
+
+/// Turns `v` by `angle` degrees, clockwise on screen.
+fn turn(v: Vec2, angle: f32) -> Vec2 {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    Vec2::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos)
+}
+
+fn horizon(painter: &egui::Painter, rect: Rect, roll: f32, pitch: f32) {
+    let painter = painter.with_clip_rect(rect);
+    let center = rect.center();
+    // everything below is laid out level and then turned against the roll
+    let at = |x: f32, y: f32| center + turn(Vec2::new(x, y + pitch * PITCH_SCALE), -roll);
+    let far = rect.width() * 2.;
+
+    painter.rect_filled(rect, 0., SKY);
+    painter.add(Shape::convex_polygon(
+        vec![at(-far, 0.), at(far, 0.), at(far, far), at(-far, far)],
+        GROUND,
+        Stroke::NONE,
+    ));
+    painter.line_segment([at(-far, 0.), at(far, 0.)], Stroke::new(2., MARKING));
+
+    let font = FontId::proportional(11.);
+    for step in (-18..=18).filter(|s| *s != 0) {
+        let deg = step as f32 * 5.;
+        // only the rungs near the current pitch fit on the instrument
+        if (deg - pitch).abs() > 30. {
+            continue;
+        }
+        let y = -deg * PITCH_SCALE;
+        let half = if step % 2 == 0 { 30. } else { 14. };
+        painter.line_segment([at(-half, y), at(half, y)], Stroke::new(1.5, MARKING));
+        if step % 2 == 0 {
+            let label = format!("{}", deg.abs());
+            painter.text(
+                at(-half - 4., y),
+                Align2::RIGHT_CENTER,
+                &label,
+                font.clone(),
+                MARKING,
+            );
+            painter.text(
+                at(half + 4., y),
+                Align2::LEFT_CENTER,
+                &label,
+                font.clone(),
+                MARKING,
+            );
+        }
+    }
+}
+
+fn roll_scale(painter: &egui::Painter, rect: Rect, roll: f32) {
+    let center = rect.center();
+    let radius = rect.width() * 0.42;
+    let up = Vec2::new(0., -radius);
+    for tick in ROLL_TICKS.iter().flat_map(|t| [*t, -*t]) {
+        let length = if tick % 30. == 0. { 12. } else { 7. };
+        let dir = turn(up, tick);
+        let inner = center + dir * (1. - length / radius);
+        painter.line_segment([inner, center + dir], Stroke::new(2., MARKING));
+    }
+    // the pointer stays with the horizon, so it reads the bank angle off
+    // the fixed scale
+    let dir = turn(up, -roll).normalized();
+    let side = turn(dir, 90.);
+    let tip = center + dir * (radius - 2.);
+    let base = tip - dir * 10.;
+    painter.add(Shape::convex_polygon(
+        vec![tip, base + side * 6., base - side * 6.],
+        SYMBOL,
+        Stroke::NONE,
+    ));
+}
+
+fn aircraft_symbol(painter: &egui::Painter, rect: Rect) {
+    let c = rect.center();
+    let stroke = Stroke::new(3., SYMBOL);
+    painter.line_segment([c + Vec2::new(-60., 0.), c + Vec2::new(-22., 0.)], stroke);
+    painter.line_segment([c + Vec2::new(-22., 0.), c + Vec2::new(-12., 10.)], stroke);
+    painter.line_segment([c + Vec2::new(60., 0.), c + Vec2::new(22., 0.)], stroke);
+    painter.line_segment([c + Vec2::new(22., 0.), c + Vec2::new(12., 10.)], stroke);
+    painter.circle_filled(c, 3., SYMBOL);
+}
+
+fn heading_tape(painter: &egui::Painter, rect: Rect, heading: f32) {
+    let painter = painter.with_clip_rect(rect);
+    painter.rect_filled(rect, 0., Color32::from_black_alpha(200));
+    let font = FontId::proportional(11.);
+    let span = rect.width() / 2. / HEADING_SCALE;
+    let first = ((heading - span) / 5.).floor() as i32;
+    let last = ((heading + span) / 5.).ceil() as i32;
+    for step in first..=last {
+        let deg = step as f32 * 5.;
+        let x = rect.center().x + (deg - heading) * HEADING_SCALE;
+        let major = step % 2 == 0;
+        let length = if major { 10. } else { 5. };
+        painter.line_segment(
+            [Pos2::new(x, rect.top()), Pos2::new(x, rect.top() + length)],
+            Stroke::new(1.5, MARKING),
+        );
+        if major {
+            let label = match step.rem_euclid(72) {
+                0 => "N".to_owned(),
+                18 => "E".to_owned(),
+                36 => "S".to_owned(),
+                54 => "W".to_owned(),
+                s => format!("{}", s * 5),
+            };
+            painter.text(
+                Pos2::new(x, rect.bottom() - 4.),
+                Align2::CENTER_BOTTOM,
+                label,
+                font.clone(),
+                MARKING,
+            );
+        }
+    }
+    let c = rect.center_top();
+    painter.add(Shape::convex_polygon(
+        vec![
+            c + Vec2::new(0., 12.),
+            c + Vec2::new(-6., 0.),
+            c + Vec2::new(6., 0.),
+        ],
+        SYMBOL,
+        Stroke::NONE,
+    ));
+}
+
+pub fn hud_ui(
+    mut contexts: EguiContexts,
+    mut selected: ResMut<SelectedDrone>,
+    drones: Query<(Entity, &Transform, &DroneLabel)>,
+) {
+    let ctx = contexts.ctx_mut();
+
+    egui::Window::new("Attitude")
+        .default_pos([290., 20.])
+        .resizable(false)
+        .show(ctx, |ui| {
+            let shown = selected.get(drones.iter().map(|(e, _, _)| e));
+            let Some((_, transform, label)) = shown.and_then(|e| drones.get(e).ok()) else {
+                ui.label("no drones");
+                return;
+            };
+            egui::ComboBox::from_id_source("hud_drone")
+                .selected_text(&label.0)
+                .show_ui(ui, |ui| {
+                    for (entity, _, label) in drones.iter() {
+                        if ui
+                            .selectable_label(shown == Some(entity), &label.0)
+                            .clicked()
+                        {
+                            selected.0 = Some(entity);
+                        }
+                    }
+                });
+
+            let (roll, pitch, yaw) = euler_degrees(transform.rotation);
+            let heading = yaw.rem_euclid(360.);
+            let (response, painter) =
+                ui.allocate_painter(Vec2::new(SIZE, SIZE + TAPE_HEIGHT), egui::Sense::hover());
+            let adi = Rect::from_min_size(response.rect.min, Vec2::splat(SIZE));
+            let tape = Rect::from_min_size(adi.left_bottom(), Vec2::new(SIZE, TAPE_HEIGHT));
+            horizon(&painter, adi, roll, pitch);
+            roll_scale(&painter, adi, roll);
+            aircraft_symbol(&painter, adi);
+            heading_tape(&painter, tape, heading);
+
+            ui.horizontal(|ui| {
+                let value = |v: f32| egui::RichText::new(format!("{v:+7.1}°")).monospace();
+                ui.label("roll");
+                ui.label(value(roll));
+                ui.label("pitch");
+                ui.label(value(pitch));
+                ui.label("yaw");
+                ui.label(value(heading));
+            });
+        });
+}
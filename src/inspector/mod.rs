@@ -0,0 +1,157 @@
+use bevy::prelude::*;
+use bevy_egui::egui::{self, RichText};
+use bevy_egui::EguiContexts;
+
+use crate::camera::camera_input;
+use crate::gyro::{DroneLabel, DroneVariant, GyroComponent, GyroState, SelectedDrone, Source};
+use crate::tare::TareAction;
+
+const VARIANTS: [DroneVariant; 4] = [
+    DroneVariant::Both,
+    DroneVariant::Gyro,
+    DroneVariant::Acc,
+    DroneVariant::Reference,
+];
+
+pub struct InspectorPlugin;
+
+impl Plugin for InspectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, inspector_ui.before(camera_input));
+    }
+}
+
+fn degrees(angle: Option<f32>) -> String {
+    angle.map_or("-".to_owned(), |a| format!("{:+.1}°", a.to_degrees()))
+}
+
+/// Every drone with its estimator state, each editable on its own.
+pub fn inspector_ui(
+    mut contexts: EguiContexts,
+    mut selected: ResMut<SelectedDrone>,
+    sources: Query<&Source>,
+    mut drones: Query<(Entity, &mut DroneLabel, &mut GyroComponent, &mut Transform)>,
+) {
+    egui::Window::new("Inspector")
+        .default_pos([290., 380.])
+        .vscroll(true)
+        .show(contexts.ctx_mut(), |ui| {
+            if drones.is_empty() {
+                ui.label("no drones");
+                return;
+            }
+            let shown = selected.get(drones.iter().map(|(e, ..)| e));
+            for (entity, mut label, mut gyro, mut transform) in drones.iter_mut() {
+                let header = RichText::new(&label.0).strong();
+                let response = egui::CollapsingHeader::new(header)
+                    .id_source(entity)
+                    .default_open(shown == Some(entity))
+                    .show(ui, |ui| {
+                        egui::Grid::new(("inspector", entity)).show(ui, |ui| {
+                            ui.label("label");
+                            ui.text_edit_singleline(&mut label.0);
+                            ui.end_row();
+
+                            ui.label("source");
+                            ui.label(sources.get(gyro.source).map_or("?", |s| &s.label));
+                            ui.end_row();
+
+                            ui.label("estimator");
+                            let mut variant = gyro.variant;
+                            egui::ComboBox::from_id_source(("inspector_variant", entity))
+                                .selected_text(format!("{variant:?}"))
+                                .show_ui(ui, |ui| {
+                                    for v in VARIANTS {
+                                        ui.selectable_value(&mut variant, v, format!("{v:?}"));
+                                    }
+                                });
+                            if variant != gyro.variant {
+                                gyro.variant = variant;
+                                gyro.reset();
+                            }
+                            ui.end_row();
+
+                            ui.label("state");
+                            match &gyro.state {
+                                GyroState::Calibration(samples) => ui.label(format!(
+                                    "calibrating {}/{}",
+                                    samples.len(),
+                                    gyro.calibration_samples
+                                )),
+                                GyroState::Active => ui.label("active"),
+                            };
+                            ui.end_row();
+
+                            ui.label("angles");
+                            ui.label(format!(
+                                "x {}  y {}  z {}",
+                                degrees(gyro.x),
+                                degrees(gyro.y),
+                                degrees(gyro.z)
+                            ));
+                            ui.end_row();
+
+                            if matches!(gyro.variant, DroneVariant::Gyro | DroneVariant::Both) {
+                                ui.label("gyro bias");
+                                ui.horizontal(|ui| {
+                                    let (x, y, z) = &mut gyro.offset;
+                                    for v in [x, y, z] {
+                                        ui.add(egui::DragValue::new(v).speed(0.01).suffix(" °/s"));
+                                    }
+                                });
+                                ui.end_row();
+                            }
+                            if gyro.variant == DroneVariant::Both {
+                                ui.label("acc weight");
+                                ui.add(egui::Slider::new(&mut gyro.acc_weight, 0.0..=1.0));
+                                ui.end_row();
+                            }
+                        });
+
+                        ui.horizontal(|ui| {
+                            if ui
+                                .button("Reset")
+                                .on_hover_text("start the attitude over")
+                                .clicked()
+                            {
+                                gyro.reset();
+                                transform.rotation = gyro.displayed();
+                            }
+                            if ui
+                                .add_enabled(
+                                    gyro.variant != DroneVariant::Reference,
+                                    egui::Button::new("Recalibrate"),
+                                )
+                                .on_hover_text("measure the gyro bias again, keep the device still")
+                                .clicked()
+                            {
+                                gyro.recalibrate();
+                                transform.rotation = gyro.displayed();
+                            }
+                            if ui
+                                .add_enabled(shown != Some(entity), egui::Button::new("Select"))
+                                .clicked()
+                            {
+                                selected.0 = Some(entity);
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            for action in TareAction::ALL {
+                                if ui
+                                    .add_enabled(
+                                        action.applies_to(gyro.variant),
+                                        egui::Button::new(action.name()),
+                                    )
+                                    .clicked()
+                                {
+                                    action.apply(&mut gyro, &mut transform);
+                                }
+                            }
+                        });
+                    });
+                if response.header_response.clicked() {
+                    selected.0 = Some(entity);
+                }
+            }
+        });
+}
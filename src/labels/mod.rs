@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Align2, Color32, FontId, Id, LayerId, Order, Pos2, Stroke};
+use bevy_egui::{EguiContexts, EguiSettings};
+
+use crate::camera::{camera_input, pointer_in_view};
+use crate::gyro::{
+    euler_degrees, DroneColor, DroneLabel, DroneVariant, GyroComponent, SelectedDrone,
+};
+
+/// How far above its origin a drone's label floats, world units.
+const LABEL_HEIGHT: f32 = 2.5;
+/// Screen points within which a click picks a drone.
+const PICK_RADIUS: f32 = 40.;
+
+#[derive(Resource)]
+pub struct LabelSettings {
+    pub labels: bool,
+    pub angles: bool,
+}
+
+impl Default for LabelSettings {
+    fn default() -> Self {
+        Self {
+            labels: true,
+            angles: true,
+        }
+    }
+}
+
+pub struct LabelsPlugin;
+
+impl Plugin for LabelsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LabelSettings>()
+            .add_systems(Update, (labels_draw.after(camera_input), legend_ui));
+    }
+}
+
+/// What each estimator shows, for the legend.
+pub fn describe(variant: DroneVariant) -> &'static str {
+    match variant {
+        DroneVariant::Both => "gyro fused with accelerometer",
+        DroneVariant::Gyro => "gyro integration only, drifts",
+        DroneVariant::Acc => "accelerometer tilt only, no yaw",
+        DroneVariant::Reference => "attitude reported by the device",
+    }
+}
+
+/// Converts a Bevy colour for egui, dropping alpha.
+pub fn color32(color: Color) -> Color32 {
+    let [r, g, b, _] = color.as_rgba_u8();
+    Color32::from_rgb(r, g, b)
+}
+
+/// Writes a label over every drone and selects the drone clicked on.
+pub fn labels_draw(
+    mut contexts: EguiContexts,
+    egui_settings: Res<EguiSettings>,
+    settings: Res<LabelSettings>,
+    mut selected: ResMut<SelectedDrone>,
+    camera: Query<(&Camera, &GlobalTransform)>,
+    drones: Query<(Entity, &Transform, &DroneLabel, &DroneColor, &GyroComponent)>,
+) {
+    let Ok((camera, camera_transform)) = camera.get_single() else {
+        return;
+    };
+    let ctx = contexts.ctx_mut();
+    let scale = egui_settings.scale_factor as f32;
+    let to_screen = |p: Vec3| {
+        camera
+            .world_to_viewport(camera_transform, p)
+            .map(|v| Pos2::new(v.x / scale, v.y / scale))
+    };
+    let shown = selected.get(drones.iter().map(|(e, ..)| e));
+
+    let click = ctx
+        .input(|i| {
+            i.pointer
+                .primary_clicked()
+                .then(|| i.pointer.interact_pos())
+        })
+        .flatten()
+        .filter(|_| pointer_in_view(ctx));
+    let mut picked: Option<(f32, Entity)> = None;
+
+    let painter = ctx.layer_painter(LayerId::new(Order::Background, Id::new("drone_labels")));
+    let font = FontId::proportional(13.);
+    let small = FontId::monospace(11.);
+    for (entity, transform, label, color, gyro) in drones.iter() {
+        let origin = to_screen(transform.translation);
+        let anchor = to_screen(transform.translation + Vec3::Y * LABEL_HEIGHT);
+        if let Some(click) = click {
+            for pos in origin.iter().chain(anchor.iter()) {
+                let distance = pos.distance(click);
+                if distance < PICK_RADIUS && !picked.is_some_and(|(d, _)| d <= distance) {
+                    picked = Some((distance, entity));
+                }
+            }
+        }
+
+        let Some(anchor) = anchor.filter(|_| settings.labels) else {
+            continue;
+        };
+        let mut text = label.0.clone();
+        if !text.contains(&format!("{:?}", gyro.variant)) {
+            text = format!("{text} ({:?})", gyro.variant);
+        }
+        let rect = painter.text(
+            anchor,
+            Align2::CENTER_BOTTOM,
+            text,
+            font.clone(),
+            color32(color.0),
+        );
+        if shown == Some(entity) {
+            painter.rect_stroke(rect.expand(2.), 2., Stroke::new(1., Color32::WHITE));
+        }
+        if settings.angles {
+            let (roll, pitch, yaw) = euler_degrees(transform.rotation);
+            painter.text(
+                anchor + egui::vec2(0., 2.),
+                Align2::CENTER_TOP,
+                format!("r{roll:+6.1} p{pitch:+6.1} y{yaw:+6.1}"),
+                small.clone(),
+                Color32::WHITE,
+            );
+        }
+    }
+
+    if let Some((_, entity)) = picked {
+        selected.0 = Some(entity);
+    }
+}
+
+pub fn legend_ui(
+    mut contexts: EguiContexts,
+    mut settings: ResMut<LabelSettings>,
+    mut selected: ResMut<SelectedDrone>,
+    drones: Query<(Entity, &DroneLabel, &DroneColor, &GyroComponent)>,
+) {
+    egui::Window::new("Legend")
+        .default_pos([560., 440.])
+        .show(contexts.ctx_mut(), |ui| {
+            let shown = selected.get(drones.iter().map(|(e, ..)| e));
+            egui::Grid::new("legend").show(ui, |ui| {
+                for (entity, label, color, gyro) in drones.iter() {
+                    let (rect, _) =
+                        ui.allocate_exact_size(egui::vec2(12., 12.), egui::Sense::hover());
+                    ui.painter().rect_filled(rect, 2., color32(color.0));
+                    if ui
+                        .selectable_label(shown == Some(entity), &label.0)
+                        .clicked()
+                    {
+                        selected.0 = Some(entity);
+                    }
+                    ui.label(format!("{:?}", gyro.variant));
+                    ui.weak(describe(gyro.variant));
+                    ui.end_row();
+                }
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut settings.labels, "labels");
+                ui.checkbox(&mut settings.angles, "angles");
+            });
+            ui.label("click a drone to select it");
+        });
+}
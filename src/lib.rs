@@ -1 +1,18 @@
-pub mod gyro;
\ No newline at end of file
+pub mod allan;
+pub mod broadcast;
+pub mod camera;
+pub mod config;
+pub mod console;
+pub mod filter;
+pub mod gyro;
+pub mod hud;
+pub mod inspector;
+pub mod labels;
+pub mod model;
+pub mod plots;
+pub mod reference;
+pub mod series;
+pub mod session;
+pub mod spectrum;
+pub mod tare;
+pub mod trails;
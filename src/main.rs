@@ -3,46 +3,165 @@
 
 use std::io::Cursor;
 
+use bevy::log::LogPlugin;
 use bevy::prelude::*;
 use bevy::window::PrimaryWindow;
 use bevy::winit::WinitWindows;
 use bevy::DefaultPlugins;
-use bevy_egui::egui::{Color32, Frame, RichText};
+use bevy_egui::egui::{Color32, RichText};
 use bevy_egui::{egui, EguiContexts, EguiPlugin};
-// use bevy_infinite_grid::{InfiniteGrid, InfiniteGridBundle, InfiniteGridPlugin};
 use bevy_obj::ObjPlugin;
-use gui::gyro::{open, open_tcp, GyroComponent, GyroPlugin, Port};
+use gui::broadcast::{serve, Broadcast, BroadcastFormat, BroadcastPlugin};
+use gui::camera::{CameraPlugin, OrbitCamera};
+use gui::config::{exit_with_usage, usage, Config, ConfigPlugin};
+use gui::console::{ConsolePlugin, LogConsole};
+use gui::filter::FilterPlugin;
+use gui::gyro::{
+    listen_tcp, open, open_mavlink, open_mavlink_tcp, open_mavlink_udp, open_msp, open_msp_tcp,
+    open_tcp, GyroComponent, GyroPlugin, Layout, LayoutFile, ListenSource, Port, Source,
+    SourceSpec, TcpServer,
+};
+use gui::hud::HudPlugin;
+use gui::inspector::InspectorPlugin;
+use gui::labels::LabelsPlugin;
+use gui::model::ModelPlugin;
+use gui::plots::PlotsPlugin;
+use gui::reference::ReferencePlugin;
+use gui::session::SessionPlugin;
+use gui::tare::TarePlugin;
+use gui::trails::TrailsPlugin;
 use winit::window::Icon;
 
 fn main() {
-    App::new()
-        .insert_resource(Port {
-            // rx: Some(open(std::path::Path::new("/dev/ttyUSB0"), 115200)),
-            rx: Some(open_tcp()),
+    let argv = std::env::args().collect::<Vec<_>>();
+    if argv.iter().any(|a| a == "--help" || a == "-h") {
+        print!("{}", usage());
+        return;
+    }
+    let config = Config::load(&argv).unwrap_or_else(|e| exit_with_usage(e));
+    // before the sources open, their errors belong in the console too
+    let console = LogConsole::install(&config.log).unwrap_or_else(|e| exit_with_usage(e));
+
+    let mut app = App::new();
+    // every source gets its own row of drones
+    for spec in config.source_specs() {
+        let (label, spec) = match spec.split_once('=') {
+            Some((label, spec)) => (label.to_owned(), spec.to_owned()),
+            None => (spec.clone(), spec),
+        };
+        let spec: SourceSpec = spec.parse().unwrap_or_else(|e| exit_with_usage(e));
+        let mut port = Port {
+            rx: None,
             last_transmition: None,
-        })
+        };
+        match &spec {
+            SourceSpec::Serial { path, baudrate } => {
+                port.rx = Some(open(std::path::Path::new(path), *baudrate));
+            }
+            SourceSpec::Tcp(addr) => {
+                port.rx = Some(open_tcp(addr.as_str()));
+            }
+            SourceSpec::Msp {
+                path,
+                baudrate,
+                version,
+            } => {
+                port.rx = Some(open_msp(std::path::Path::new(path), *baudrate, *version));
+            }
+            SourceSpec::MspTcp { addr, version } => {
+                port.rx = Some(open_msp_tcp(addr.as_str(), *version));
+            }
+            SourceSpec::Mavlink { path, baudrate } => {
+                port.rx = Some(open_mavlink(std::path::Path::new(path), *baudrate));
+            }
+            SourceSpec::MavlinkUdp(addr) => {
+                port.rx = Some(open_mavlink_udp(addr.as_str()));
+            }
+            SourceSpec::MavlinkTcp(addr) => {
+                port.rx = Some(open_mavlink_tcp(addr.as_str()));
+            }
+            SourceSpec::Listen(addr) => {
+                if app.world.contains_resource::<TcpServer>() {
+                    exit_with_usage("only one listening source is supported");
+                }
+                let server = listen_tcp(addr.as_str()).unwrap_or_else(|e| exit_with_usage(e));
+                app.insert_resource(server);
+                app.world
+                    .spawn((ListenSource, Source { label, spec }, port));
+                continue;
+            }
+        }
+        app.world.spawn((Source { label, spec }, port));
+    }
+
+    if let Some(path) = config.ui.layout.clone() {
+        if path.exists() {
+            app.insert_resource(Layout::load(&path).unwrap_or_else(|e| exit_with_usage(e)));
+        }
+        app.insert_resource(LayoutFile(path));
+    }
+
+    if let Some(addr) = &config.broadcast.addr {
+        let format = config
+            .broadcast
+            .format
+            .as_ref()
+            .map(|f| f.parse().unwrap_or_else(|e| exit_with_usage(e)))
+            .unwrap_or(BroadcastFormat::JsonLines);
+        let broadcast = serve(addr.as_str(), format).unwrap_or_else(|e| exit_with_usage(e));
+        app.insert_resource(broadcast);
+    }
+
+    let window = Window {
+        title: config.ui.title.clone(),
+        resolution: (config.ui.width, config.ui.height).into(),
+        canvas: Some("#bevy".to_owned()),
+        ..default()
+    };
+
+    app.insert_resource(config)
+        .insert_resource(console)
         // .insert_resource(Msaa::Off)
         // .insert_resource(ClearColor(
         //     Color::rgb(1., 0.4, 0.4),
         // ))
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "Bevy game".to_string(), // ToDo
-                resolution: (800., 600.).into(),
-                canvas: Some("#bevy".to_owned()),
-                ..default()
-            }),
-            ..default()
-        }))
-        // .add_plugins(InfiniteGridPlugin)
+        .add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: Some(window),
+                    ..default()
+                })
+                .disable::<LogPlugin>(),
+        )
         .add_plugins(EguiPlugin)
         .add_plugins(ObjPlugin)
+        .add_plugins(ConfigPlugin)
+        .add_plugins(ConsolePlugin)
         .add_plugins(GyroPlugin)
+        .add_plugins(BroadcastPlugin)
+        .add_plugins(FilterPlugin)
+        .add_plugins(PlotsPlugin)
+        .add_plugins(HudPlugin)
+        .add_plugins(InspectorPlugin)
+        .add_plugins(LabelsPlugin)
+        .add_plugins(CameraPlugin)
+        .add_plugins(ReferencePlugin)
+        .add_plugins(ModelPlugin)
+        .add_plugins(TrailsPlugin)
+        .add_plugins(TarePlugin)
+        .add_plugins(SessionPlugin)
         .add_systems(
             Startup,
             (set_window_icon, setup_camera, configure_visuals_system),
         )
-        .add_systems(Update, (ui_example_system,))
+        .add_systems(
+            Update,
+            (
+                sources_ui_system,
+                devices_ui_system.run_if(resource_exists::<TcpServer>()),
+                broadcast_ui_system.run_if(resource_exists::<Broadcast>()),
+            ),
+        )
         .run();
 }
 
@@ -67,20 +186,14 @@ fn set_window_icon(
 }
 
 fn setup_camera(mut commands: Commands) {
-    // commands.spawn(
-    //     InfiniteGridBundle {
-    //         grid: InfiniteGrid {
-    //             // shadow_color: None,
-    //             ..Default::default()
-    //         },
-    //         ..Default::default()
-    //     },
-    // );
-    commands.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(0., 0., -15.).looking_at(Vec3::new(0., 0., 0.), Vec3::Y),
-        // transform: Transform::from_xyz(0., 15., 0.).looking_at(Vec3::new(0., 0., 0.), Vec3::X),
-        ..default()
-    });
+    commands.spawn((
+        Camera3dBundle {
+            transform: Transform::from_xyz(0., 0., -15.).looking_at(Vec3::new(0., 0., 0.), Vec3::Y),
+            // transform: Transform::from_xyz(0., 15., 0.).looking_at(Vec3::new(0., 0., 0.), Vec3::X),
+            ..default()
+        },
+        OrbitCamera::default(),
+    ));
 }
 
 fn configure_visuals_system(mut contexts: EguiContexts) {
@@ -90,20 +203,85 @@ fn configure_visuals_system(mut contexts: EguiContexts) {
     });
 }
 
-fn ui_example_system(mut contexts: EguiContexts, mut query: Query<&mut GyroComponent>) {
+fn devices_ui_system(mut contexts: EguiContexts, server: Res<TcpServer>) {
+    let ctx = contexts.ctx_mut();
+    let mut state = server.state.lock().unwrap();
+
+    egui::Window::new("Devices")
+        .default_pos([560., 20.])
+        .show(ctx, |ui| {
+            ui.label(format!("Listening on {}", server.local_addr));
+            if state.peers.is_empty() {
+                ui.label("Waiting for a device to connect...");
+            }
+            let mut selected = state.selected;
+            for (i, peer) in state.peers.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut selected, Some(i), peer.addr.to_string());
+                    let status = match (peer.connected, peer.last_sample) {
+                        (false, _) => RichText::new("disconnected").color(Color32::RED),
+                        (true, None) => RichText::new("connected").color(Color32::YELLOW),
+                        (true, Some(t)) => {
+                            RichText::new(format!("{:.1}s ago", t.elapsed().as_secs_f32()))
+                                .color(Color32::GREEN)
+                        }
+                    };
+                    ui.label(status);
+                });
+            }
+            state.selected = selected;
+        });
+}
+
+fn broadcast_ui_system(mut contexts: EguiContexts, broadcast: Res<Broadcast>) {
+    let ctx = contexts.ctx_mut();
+    let clients = broadcast.clients.lock().unwrap();
+
+    egui::Window::new("Broadcast")
+        .default_pos([560., 300.])
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "Serving {:?} on {}",
+                broadcast.format, broadcast.local_addr
+            ));
+            if clients.is_empty() {
+                ui.label("No clients");
+            }
+            for client in clients.iter() {
+                ui.label(client.addr.to_string());
+            }
+        });
+}
+
+fn sources_ui_system(
+    mut contexts: EguiContexts,
+    sources: Query<(Entity, &Source, &Port)>,
+    drones: Query<&GyroComponent>,
+) {
     let ctx = contexts.ctx_mut();
-    let mut gyro = query.iter_mut().next().unwrap();
 
-    egui::CentralPanel::default()
-        .frame(Frame::default().fill(Color32::TRANSPARENT))
+    egui::Window::new("Sources")
+        .default_pos([560., 160.])
         .show(ctx, |ui| {
-            let rt = egui::widgets::Label::new(
-                RichText::new("Accelerometer weight in camputations")
-                    .background_color(Color32::BLUE)
-                    .color(Color32::YELLOW)
-                    .size(15.),
-            );
-            ui.add(rt);
-            ui.add(egui::Slider::new(&mut gyro.acc_weight, 0.0..=1.0));
+            for (entity, source, port) in sources.iter() {
+                ui.horizontal(|ui| {
+                    ui.strong(&source.label);
+                    if source.label != source.spec.to_string() {
+                        ui.label(source.spec.to_string());
+                    }
+                    let drones = drones.iter().filter(|d| d.source == entity).count();
+                    ui.label(format!("{drones} drones"));
+                    let listening = matches!(source.spec, SourceSpec::Listen(_));
+                    let status = match port.last_transmition {
+                        _ if port.rx.is_none() && !listening => {
+                            RichText::new("closed, see the console").color(Color32::RED)
+                        }
+                        None => RichText::new("no data").color(Color32::YELLOW),
+                        Some(t) => RichText::new(format!("{:.1}s ago", t.elapsed().as_secs_f32()))
+                            .color(Color32::GREEN),
+                    };
+                    ui.label(status);
+                });
+            }
         });
 }
@@ -0,0 +1,280 @@
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_egui::EguiContexts;
+
+use crate::gyro::DroneColor;
+
+const DEFAULT_MODEL: &str = "Drone2.obj";
+
+/// The airframe every drone is drawn with. The model hangs off the drone as
+/// a child, so `rotation` and `scale` line it up with the body axes without
+/// touching the attitude the estimators write.
+#[derive(Resource)]
+pub struct DroneModel {
+    /// An OBJ, or a glTF (`.gltf`/`.glb`) whose first scene is used; relative
+    /// to `assets` or absolute.
+    pub path: String,
+    pub scale: f32,
+    /// Model-to-body rotation, XYZ Euler angles in degrees.
+    pub rotation: Vec3,
+}
+
+impl Default for DroneModel {
+    fn default() -> Self {
+        Self {
+            path: DEFAULT_MODEL.to_owned(),
+            scale: 1.,
+            rotation: Vec3::ZERO,
+        }
+    }
+}
+
+impl DroneModel {
+    fn is_gltf(&self) -> bool {
+        let path = self.path.to_lowercase();
+        path.ends_with(".gltf") || path.ends_with(".glb")
+    }
+
+    fn transform(&self) -> Transform {
+        let r = self.rotation * std::f32::consts::PI / 180.;
+        Transform::from_rotation(Quat::from_euler(EulerRot::XYZ, r.x, r.y, r.z))
+            .with_scale(Vec3::splat(self.scale))
+    }
+}
+
+#[derive(Resource)]
+pub struct Lighting {
+    /// Directional light, lux.
+    pub sun: f32,
+    pub ambient: f32,
+    pub shadows: bool,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            sun: 15000.,
+            ambient: 0.3,
+            shadows: true,
+        }
+    }
+}
+
+/// The model child of a drone, and the file it was loaded from.
+#[derive(Component)]
+pub struct ModelInstance {
+    path: String,
+}
+
+/// Colour the meshes of a glTF scene are multiplied by once it has spawned.
+/// A see-through tint makes them blend and stop casting shadows.
+#[derive(Component)]
+pub struct SceneTint(pub Color);
+
+/// A mesh of a glTF scene whose material has been swapped for a copy tinted
+/// with the scene's `SceneTint`.
+#[derive(Component)]
+pub struct Tinted;
+
+#[derive(Component)]
+pub struct Sun;
+
+pub struct ModelPlugin;
+
+impl Plugin for ModelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DroneModel>()
+            .init_resource::<Lighting>()
+            .add_systems(Startup, lights_spawn)
+            .add_systems(Update, (model_sync, scene_tint, lighting_apply, model_ui));
+    }
+}
+
+pub fn lights_spawn(mut commands: Commands) {
+    commands.spawn((
+        DirectionalLightBundle {
+            transform: Transform::from_xyz(4., 10., -6.).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        },
+        Sun,
+    ));
+}
+
+pub fn lighting_apply(
+    lighting: Res<Lighting>,
+    mut ambient: ResMut<AmbientLight>,
+    mut sun: Query<&mut DirectionalLight, With<Sun>>,
+) {
+    if !lighting.is_changed() {
+        return;
+    }
+    ambient.brightness = lighting.ambient;
+    for mut light in sun.iter_mut() {
+        light.illuminance = lighting.sun;
+        light.shadows_enabled = lighting.shadows;
+    }
+}
+
+/// Gives every drone a model child, reloads them when the file changes and
+/// keeps their offset up to date.
+pub fn model_sync(
+    mut commands: Commands,
+    model: Res<DroneModel>,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    drones: Query<(Entity, &DroneColor, Option<&Children>)>,
+    mut instances: Query<(&ModelInstance, &mut Transform)>,
+) {
+    for (drone, color, children) in drones.iter() {
+        let mut current = false;
+        for child in children.into_iter().flatten() {
+            let Ok((instance, mut transform)) = instances.get_mut(*child) else {
+                continue;
+            };
+            if instance.path == model.path {
+                current = true;
+                if model.is_changed() {
+                    *transform = model.transform();
+                }
+            } else {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+        if current {
+            continue;
+        }
+
+        let instance = ModelInstance {
+            path: model.path.clone(),
+        };
+        let child = if model.is_gltf() {
+            commands
+                .spawn((
+                    SceneBundle {
+                        scene: asset_server.load(format!("{}#Scene0", model.path)),
+                        transform: model.transform(),
+                        ..default()
+                    },
+                    SceneTint(color.0),
+                    instance,
+                ))
+                .id()
+        } else {
+            commands
+                .spawn((
+                    PbrBundle {
+                        mesh: asset_server.load(&model.path),
+                        material: materials.add(StandardMaterial {
+                            base_color: color.0,
+                            perceptual_roughness: 0.6,
+                            ..default()
+                        }),
+                        transform: model.transform(),
+                        ..default()
+                    },
+                    instance,
+                ))
+                .id()
+        };
+        commands.entity(drone).add_child(child);
+    }
+}
+
+/// glTF scenes bring their own materials, shared by every drone. Once a
+/// scene has spawned, each of its meshes gets a copy multiplied by the
+/// scene's tint, so the drones, and their ghosts, can still be told apart.
+pub fn scene_tint(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    scenes: Query<(Entity, &SceneTint)>,
+    children: Query<&Children>,
+    parts: Query<&Handle<StandardMaterial>, Without<Tinted>>,
+) {
+    for (scene, tint) in scenes.iter() {
+        for part in children.iter_descendants(scene) {
+            let Ok(handle) = parts.get(part) else {
+                continue;
+            };
+            // the scene's materials may still be loading
+            let Some(mut material) = materials.get(handle).cloned() else {
+                continue;
+            };
+            let (base, tint) = (material.base_color, tint.0);
+            material.base_color = Color::rgba(
+                base.r() * tint.r(),
+                base.g() * tint.g(),
+                base.b() * tint.b(),
+                base.a() * tint.a(),
+            );
+            let mut part = commands.entity(part);
+            if tint.a() < 1. {
+                material.alpha_mode = AlphaMode::Blend;
+                part.insert(NotShadowCaster);
+            }
+            part.insert((materials.add(material), Tinted));
+        }
+    }
+}
+
+pub fn model_ui(
+    mut contexts: EguiContexts,
+    mut model: ResMut<DroneModel>,
+    mut lighting: ResMut<Lighting>,
+    mut path: Local<Option<String>>,
+) {
+    let path = path.get_or_insert_with(|| model.path.clone());
+    egui::Window::new("Model")
+        .default_pos([20., 280.])
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(path);
+                if ui.button("Load").clicked() && *path != model.path {
+                    model.path = path.clone();
+                }
+            });
+            ui.label("OBJ or glTF, relative to assets or absolute");
+
+            // only write through when something moved, so the children are
+            // not touched every frame
+            let (mut scale, mut rotation) = (model.scale, model.rotation);
+            ui.horizontal(|ui| {
+                ui.label("scale");
+                ui.add(
+                    egui::DragValue::new(&mut scale)
+                        .speed(0.01)
+                        .clamp_range(0.001..=1000.0),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.label("rotation");
+                for angle in [&mut rotation.x, &mut rotation.y, &mut rotation.z] {
+                    ui.add(
+                        egui::DragValue::new(angle)
+                            .speed(1.)
+                            .clamp_range(-180.0..=180.0)
+                            .suffix("°"),
+                    );
+                }
+            });
+            if scale != model.scale || rotation != model.rotation {
+                model.scale = scale;
+                model.rotation = rotation;
+            }
+
+            ui.separator();
+            let (mut sun, mut ambient, mut shadows) =
+                (lighting.sun, lighting.ambient, lighting.shadows);
+            ui.add(egui::Slider::new(&mut sun, 0.0..=100000.0).text("sun, lux"));
+            ui.add(egui::Slider::new(&mut ambient, 0.0..=2.0).text("ambient"));
+            ui.checkbox(&mut shadows, "shadows");
+            if (sun, ambient, shadows) != (lighting.sun, lighting.ambient, lighting.shadows) {
+                *lighting = Lighting {
+                    sun,
+                    ambient,
+                    shadows,
+                };
+            }
+        });
+}
@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_egui::egui::plot::{Legend, Line, LineStyle, Plot};
+use bevy_egui::egui::{self, Color32};
+use bevy_egui::EguiContexts;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::camera_input;
+use crate::gyro::{
+    chosen_or_first, euler_degrees, gyro_update, DroneColor, DroneLabel, GyroComponent, GyroSample,
+    Source,
+};
+use crate::labels::color32;
+use crate::series::Series;
+
+mod spectrum;
+
+pub use spectrum::{
+    show_psd, show_spectrogram, spectrum_settings_ui, spectrum_update, SpectrumSettings,
+    SpectrumView, CHANNEL_NAME, FFT_SIZES,
+};
+
+/// Where a plot panel lives on screen.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Dock {
+    Left,
+    Right,
+    Bottom,
+    Floating,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum PanelKind {
+    Gyro,
+    Acc,
+    Attitude,
+    Spectrum,
+    Spectrogram,
+}
+
+impl PanelKind {
+    pub fn title(&self) -> &'static str {
+        match self {
+            PanelKind::Gyro => "Raw gyro",
+            PanelKind::Acc => "Raw accelerometer",
+            PanelKind::Attitude => "Estimated attitude",
+            PanelKind::Spectrum => "Spectrum",
+            PanelKind::Spectrogram => "Spectrogram",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlotPanel {
+    pub kind: PanelKind,
+    pub open: bool,
+    pub dock: Dock,
+}
+
+#[derive(Resource)]
+pub struct PlotSettings {
+    pub panels: Vec<PlotPanel>,
+    /// Which source the panels show; the first one when unset.
+    pub source: Option<Entity>,
+    /// Seconds of device time kept and shown.
+    pub window: f64,
+    /// Draws the unfiltered gyro and accelerometer behind the filtered ones.
+    pub show_raw: bool,
+    pub spectrum: SpectrumSettings,
+}
+
+impl Default for PlotSettings {
+    fn default() -> Self {
+        Self {
+            panels: vec![
+                PlotPanel {
+                    kind: PanelKind::Gyro,
+                    open: false,
+                    dock: Dock::Bottom,
+                },
+                PlotPanel {
+                    kind: PanelKind::Acc,
+                    open: false,
+                    dock: Dock::Bottom,
+                },
+                PlotPanel {
+                    kind: PanelKind::Attitude,
+                    open: false,
+                    dock: Dock::Right,
+                },
+                PlotPanel {
+                    kind: PanelKind::Spectrum,
+                    open: false,
+                    dock: Dock::Floating,
+                },
+                PlotPanel {
+                    kind: PanelKind::Spectrogram,
+                    open: false,
+                    dock: Dock::Floating,
+                },
+            ],
+            source: None,
+            window: 10.,
+            show_raw: false,
+            spectrum: SpectrumSettings::default(),
+        }
+    }
+}
+
+/// Everything plotted for one source, against its device time.
+#[derive(Default)]
+pub struct SourceHistory {
+    pub time: f64,
+    pub gyro: [Series; 3],
+    pub acc: [Series; 3],
+    /// Gyro x, y, z and acc x, y, z as they arrived, before filtering.
+    pub raw: [Series; 6],
+    /// Roll, pitch and yaw of every drone bound to the source, in degrees.
+    pub drones: HashMap<Entity, [Series; 3]>,
+}
+
+impl SourceHistory {
+    /// Gyro x, y, z then acc x, y, z, filtered unless `raw` is set.
+    pub fn channel(&self, i: usize, raw: bool) -> &Series {
+        if raw {
+            &self.raw[i]
+        } else if i < 3 {
+            &self.gyro[i]
+        } else {
+            &self.acc[i - 3]
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PlotHistory {
+    pub sources: HashMap<Entity, SourceHistory>,
+}
+
+pub struct PlotsPlugin;
+
+impl Plugin for PlotsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlotSettings>()
+            .init_resource::<PlotHistory>()
+            .init_resource::<SpectrumView>()
+            .add_systems(
+                Update,
+                (
+                    plots_record.after(gyro_update),
+                    spectrum_update.after(plots_record),
+                    plots_ui.after(spectrum_update).before(camera_input),
+                ),
+            );
+    }
+}
+
+pub fn plots_record(
+    settings: Res<PlotSettings>,
+    mut history: ResMut<PlotHistory>,
+    mut samples: EventReader<GyroSample>,
+    drones: Query<(), With<GyroComponent>>,
+) {
+    for GyroSample {
+        source,
+        data: v,
+        raw,
+        attitudes,
+    } in samples.iter()
+    {
+        let h = history.sources.entry(*source).or_default();
+        h.time += v[12] as f64;
+        let t = h.time;
+
+        for (series, value) in h.gyro.iter_mut().zip(&v[0..3]) {
+            series.push(t, *value as f64);
+            series.trim(settings.window);
+        }
+        for (series, value) in h.acc.iter_mut().zip(&v[3..6]) {
+            series.push(t, *value as f64);
+            series.trim(settings.window);
+        }
+        for (series, value) in h.raw.iter_mut().zip(&raw[0..6]) {
+            series.push(t, *value as f64);
+            series.trim(settings.window);
+        }
+        for (entity, rotation) in attitudes {
+            let (roll, pitch, yaw) = euler_degrees(*rotation);
+            let angles = h.drones.entry(*entity).or_default();
+            for (series, value) in angles.iter_mut().zip([roll, pitch, yaw]) {
+                series.push(t, value as f64);
+                series.trim(settings.window);
+            }
+        }
+    }
+    for h in history.sources.values_mut() {
+        h.drones.retain(|e, _| drones.contains(*e));
+    }
+}
+
+/// The source the panels show: the chosen one while it exists, the first
+/// one otherwise.
+pub fn shown_source(
+    settings: &PlotSettings,
+    sources: impl Iterator<Item = Entity>,
+) -> Option<Entity> {
+    chosen_or_first(settings.source, sources)
+}
+
+/// Points drawn per line; anything longer is decimated.
+const MAX_DRAWN: usize = 2000;
+
+const AXIS_COLOR: [Color32; 3] = [Color32::RED, Color32::GREEN, Color32::LIGHT_BLUE];
+const AXIS_NAME: [&str; 3] = ["x", "y", "z"];
+const ANGLE_NAME: [&str; 3] = ["roll", "pitch", "yaw"];
+const ANGLE_STYLE: [LineStyle; 3] = [
+    LineStyle::Solid,
+    LineStyle::Dashed { length: 8. },
+    LineStyle::Dotted { spacing: 6. },
+];
+
+fn show_panel(
+    ui: &mut egui::Ui,
+    kind: PanelKind,
+    height: f32,
+    history: &SourceHistory,
+    drones: &Query<(&DroneLabel, &DroneColor)>,
+    spectrum: &SpectrumView,
+    show_raw: bool,
+) {
+    match kind {
+        PanelKind::Spectrum => return show_psd(ui, height, spectrum),
+        PanelKind::Spectrogram => return show_spectrogram(ui, height, spectrum),
+        _ => {}
+    }
+    Plot::new(kind.title())
+        .height(height)
+        .legend(Legend::default())
+        .show(ui, |plot_ui| match kind {
+            PanelKind::Gyro | PanelKind::Acc => {
+                let (channels, raw) = if kind == PanelKind::Gyro {
+                    (&history.gyro, &history.raw[0..3])
+                } else {
+                    (&history.acc, &history.raw[3..6])
+                };
+                if show_raw {
+                    for (i, series) in raw.iter().enumerate() {
+                        plot_ui.line(
+                            Line::new(series.decimated(MAX_DRAWN))
+                                .name(format!("{} raw", AXIS_NAME[i]))
+                                .color(AXIS_COLOR[i].linear_multiply(0.3)),
+                        );
+                    }
+                }
+                for (i, series) in channels.iter().enumerate() {
+                    plot_ui.line(
+                        Line::new(series.decimated(MAX_DRAWN))
+                            .name(AXIS_NAME[i])
+                            .color(AXIS_COLOR[i]),
+                    );
+                }
+            }
+            PanelKind::Attitude => {
+                for (entity, angles) in history.drones.iter() {
+                    let Ok((label, color)) = drones.get(*entity) else {
+                        continue;
+                    };
+                    for (i, series) in angles.iter().enumerate() {
+                        plot_ui.line(
+                            Line::new(series.decimated(MAX_DRAWN))
+                                .name(format!("{} {}", label.0, ANGLE_NAME[i]))
+                                .color(color32(color.0))
+                                .style(ANGLE_STYLE[i]),
+                        );
+                    }
+                }
+            }
+            PanelKind::Spectrum | PanelKind::Spectrogram => unreachable!(),
+        });
+}
+
+/// Draws the plot settings window and every open panel. Docked panels take
+/// their space from the central panel, so this has to run before anything
+/// that shows one.
+pub fn plots_ui(
+    mut contexts: EguiContexts,
+    mut settings: ResMut<PlotSettings>,
+    history: Res<PlotHistory>,
+    spectrum: Res<SpectrumView>,
+    sources: Query<(Entity, &Source)>,
+    drones: Query<(&DroneLabel, &DroneColor)>,
+) {
+    let ctx = contexts.ctx_mut();
+    let settings = &mut *settings;
+
+    egui::Window::new("Plots")
+        .default_pos([20., 120.])
+        .default_open(false)
+        .show(ctx, |ui| {
+            let selected = settings
+                .source
+                .and_then(|e| sources.get(e).ok())
+                .or_else(|| sources.iter().next())
+                .map_or(String::new(), |(_, s)| s.label.clone());
+            egui::ComboBox::from_label("Source")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (entity, source) in sources.iter() {
+                        ui.selectable_value(&mut settings.source, Some(entity), &source.label);
+                    }
+                });
+            ui.add(egui::Slider::new(&mut settings.window, 1.0..=120.0).text("window, s"));
+            ui.checkbox(&mut settings.show_raw, "show unfiltered");
+            for panel in settings.panels.iter_mut() {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut panel.open, panel.kind.title());
+                    egui::ComboBox::from_id_source(panel.kind.title())
+                        .selected_text(format!("{:?}", panel.dock))
+                        .show_ui(ui, |ui| {
+                            for dock in [Dock::Left, Dock::Right, Dock::Bottom, Dock::Floating] {
+                                ui.selectable_value(&mut panel.dock, dock, format!("{dock:?}"));
+                            }
+                        });
+                });
+            }
+            ui.separator();
+            ui.label("Spectrum");
+            spectrum_settings_ui(ui, &mut settings.spectrum);
+        });
+
+    let show_raw = settings.show_raw;
+    let source = shown_source(settings, sources.iter().map(|(e, _)| e));
+    let Some(history) = source.and_then(|e| history.sources.get(&e)) else {
+        return;
+    };
+
+    for dock in [Dock::Left, Dock::Right, Dock::Bottom] {
+        let panels = settings
+            .panels
+            .iter()
+            .filter(|p| p.open && p.dock == dock)
+            .map(|p| p.kind)
+            .collect::<Vec<_>>();
+        if panels.is_empty() {
+            continue;
+        }
+        let add_contents = |ui: &mut egui::Ui| {
+            let height = ui.available_height() / panels.len() as f32 - 8.;
+            for kind in panels.iter() {
+                ui.label(kind.title());
+                show_panel(
+                    ui,
+                    *kind,
+                    height - 16.,
+                    history,
+                    &drones,
+                    &spectrum,
+                    show_raw,
+                );
+            }
+        };
+        match dock {
+            Dock::Left => {
+                egui::SidePanel::left("plots_left")
+                    .resizable(true)
+                    .default_width(300.)
+                    .show(ctx, add_contents);
+            }
+            Dock::Right => {
+                egui::SidePanel::right("plots_right")
+                    .resizable(true)
+                    .default_width(300.)
+                    .show(ctx, add_contents);
+            }
+            Dock::Bottom => {
+                egui::TopBottomPanel::bottom("plots_bottom")
+                    .resizable(true)
+                    .default_height(200.)
+                    .show(ctx, |ui| {
+                        ui.columns(panels.len(), |columns| {
+                            for (ui, kind) in columns.iter_mut().zip(panels.iter()) {
+                                ui.label(kind.title());
+                                let height = ui.available_height() - 16.;
+                                show_panel(
+                                    ui, *kind, height, history, &drones, &spectrum, show_raw,
+                                );
+                            }
+                        });
+                    });
+            }
+            Dock::Floating => unreachable!(),
+        }
+    }
+
+    for panel in settings.panels.iter_mut() {
+        if panel.open && panel.dock == Dock::Floating {
+            egui::Window::new(panel.kind.title())
+                .open(&mut panel.open)
+                .default_size([400., 200.])
+                .show(ctx, |ui| {
+                    let height = ui.available_height().max(150.);
+                    show_panel(
+                        ui, panel.kind, height, history, &drones, &spectrum, show_raw,
+                    );
+                });
+        }
+    }
+}
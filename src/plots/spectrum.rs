@@ -0,0 +1,301 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_egui::egui::plot::{Line, Plot, PlotImage, PlotPoint, Text, VLine};
+use bevy_egui::egui::{self, Color32, ColorImage, TextureHandle, TextureOptions};
+use bevy_egui::EguiContexts;
+use serde::{Deserialize, Serialize};
+
+use crate::spectrum::{bin_freq, dominant_peaks, sample_rate, to_db, Peak, Spectrum, Window};
+
+use super::{shown_source, PanelKind, PlotHistory, PlotSettings};
+
+/// Raw channels, in sample and `SourceHistory::channel` order: what a
+/// spectrum can be taken of and a filter chain attached to.
+pub const CHANNEL_NAME: [&str; 6] = ["gyro x", "gyro y", "gyro z", "acc x", "acc y", "acc z"];
+
+pub const FFT_SIZES: [usize; 7] = [64, 128, 256, 512, 1024, 2048, 4096];
+
+/// Peaks below this are drift and gravity leaking through, not vibration.
+const MIN_PEAK_FREQ: f32 = 2.;
+
+/// The spectrogram colour scale spans this far below its loudest bin.
+const DYNAMIC_RANGE_DB: f32 = 60.;
+
+/// Largest texture side WebGL2 guarantees.
+const MAX_TEXTURE_SIZE: usize = 2048;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SpectrumSettings {
+    /// Index into `CHANNEL_NAME`.
+    pub channel: usize,
+    pub fft_size: usize,
+    pub window: Window,
+    pub peaks: usize,
+    /// Analyse what arrived rather than what the estimators see, so the
+    /// effect of the filters can be compared.
+    pub raw: bool,
+}
+
+impl Default for SpectrumSettings {
+    fn default() -> Self {
+        Self {
+            channel: 0,
+            fft_size: 256,
+            window: Window::Hann,
+            peaks: 3,
+            raw: false,
+        }
+    }
+}
+
+type SpectrumKey = (Entity, usize, bool, usize, Window);
+
+/// What the spectrum panels show, recomputed as samples arrive.
+#[derive(Resource, Default)]
+pub struct SpectrumView {
+    /// Source, channel, raw or filtered, FFT size and window the rest was
+    /// computed for.
+    key: Option<SpectrumKey>,
+    spectrum: Option<Spectrum>,
+    pub sample_rate: f32,
+    /// Welch PSD over the whole plot window.
+    pub psd: Vec<f32>,
+    pub peaks: Vec<Peak>,
+    /// Device time and dB bins of every spectrogram column.
+    columns: VecDeque<(f64, Vec<f32>)>,
+    texture: Option<TextureHandle>,
+}
+
+impl SpectrumView {
+    fn reset(&mut self, key: SpectrumKey) {
+        *self = Self {
+            key: Some(key),
+            spectrum: Some(Spectrum::new(key.3, key.4)),
+            texture: self.texture.take(),
+            ..default()
+        };
+    }
+}
+
+pub fn spectrum_update(
+    mut contexts: EguiContexts,
+    settings: Res<PlotSettings>,
+    history: Res<PlotHistory>,
+    mut view: ResMut<SpectrumView>,
+    sources: Query<Entity, With<crate::gyro::Source>>,
+) {
+    let shown = settings
+        .panels
+        .iter()
+        .any(|p| p.open && matches!(p.kind, PanelKind::Spectrum | PanelKind::Spectrogram));
+    if !shown {
+        return;
+    }
+    let Some(source) = shown_source(&settings, sources.iter()) else {
+        return;
+    };
+    let Some(h) = history.sources.get(&source) else {
+        return;
+    };
+
+    let s = &settings.spectrum;
+    let key = (source, s.channel, s.raw, s.fft_size, s.window);
+    if view.key != Some(key) {
+        view.reset(key);
+    }
+    let view = &mut *view;
+    let Some(spectrum) = &view.spectrum else {
+        return;
+    };
+
+    let points = h.channel(s.channel, s.raw).points();
+    let Some(fs) = sample_rate(&points) else {
+        return;
+    };
+    let values = points.iter().map(|p| p[1] as f32).collect::<Vec<_>>();
+    let Some(psd) = spectrum.welch(&values, fs) else {
+        return;
+    };
+    view.peaks = dominant_peaks(&psd, s.fft_size, fs, MIN_PEAK_FREQ, s.peaks);
+    view.psd = psd;
+    view.sample_rate = fs;
+
+    // one column per half FFT of new samples since the last column
+    let hop = s.fft_size / 2;
+    let last_t = view.columns.back().map_or(f64::NEG_INFINITY, |c| c.0);
+    let first_new = points.partition_point(|p| p[0] <= last_t);
+    let mut end = (first_new + hop - 1).max(s.fft_size - 1);
+    let mut added = false;
+    while end < points.len() {
+        let column = spectrum.periodogram(&values[end + 1 - s.fft_size..], fs);
+        view.columns
+            .push_back((points[end][0], column.into_iter().map(to_db).collect()));
+        end += hop;
+        added = true;
+    }
+    let newest = points.last().map_or(0., |p| p[0]);
+    while view
+        .columns
+        .front()
+        .is_some_and(|c| c.0 < newest - settings.window)
+    {
+        view.columns.pop_front();
+    }
+    if added {
+        let image = spectrogram_image(&view.columns);
+        match &mut view.texture {
+            Some(texture) => texture.set(image, TextureOptions::LINEAR),
+            None => {
+                view.texture = Some(contexts.ctx_mut().load_texture(
+                    "spectrogram",
+                    image,
+                    TextureOptions::LINEAR,
+                ))
+            }
+        }
+    }
+}
+
+/// Columns left to right in time, highest frequency on the top row.
+/// Averages neighbouring columns and bins as needed to stay within the
+/// texture size every GPU, WebGL2 included, supports.
+fn spectrogram_image(columns: &VecDeque<(f64, Vec<f32>)>) -> ColorImage {
+    let bins = columns.front().map_or(0, |c| c.1.len());
+    let x_step = columns.len().div_ceil(MAX_TEXTURE_SIZE).max(1);
+    let y_step = bins.div_ceil(MAX_TEXTURE_SIZE).max(1);
+    let width = columns.len().div_ceil(x_step);
+    let height = bins.div_ceil(y_step);
+
+    let mut cells = vec![0f32; width * height];
+    let mut counts = vec![0u32; width * height];
+    for (i, (_, column)) in columns.iter().enumerate() {
+        for (k, db) in column.iter().enumerate() {
+            let cell = (k / y_step) * width + i / x_step;
+            cells[cell] += db;
+            counts[cell] += 1;
+        }
+    }
+    for (cell, count) in cells.iter_mut().zip(&counts) {
+        *cell /= (*count).max(1) as f32;
+    }
+
+    let loudest = cells.iter().fold(f32::NEG_INFINITY, |m, v| m.max(*v));
+    let mut image = ColorImage::new([width, height], Color32::BLACK);
+    for (cell, db) in cells.iter().enumerate() {
+        let (x, y) = (cell % width, cell / width);
+        let level = 1. - (loudest - db) / DYNAMIC_RANGE_DB;
+        image[(x, height - 1 - y)] = heat(level.clamp(0., 1.));
+    }
+    image
+}
+
+/// Black through blue, red and yellow to white.
+fn heat(level: f32) -> Color32 {
+    const STOPS: [[f32; 3]; 5] = [
+        [0., 0., 0.],
+        [0., 0., 160.],
+        [220., 0., 0.],
+        [255., 220., 0.],
+        [255., 255., 255.],
+    ];
+    let x = level * (STOPS.len() - 1) as f32;
+    let i = (x as usize).min(STOPS.len() - 2);
+    let f = x - i as f32;
+    let [r, g, b] = [0, 1, 2].map(|c| (STOPS[i][c] + (STOPS[i + 1][c] - STOPS[i][c]) * f) as u8);
+    Color32::from_rgb(r, g, b)
+}
+
+fn peak_label(peak: &Peak) -> String {
+    format!("{:.1} Hz", peak.freq)
+}
+
+pub fn show_psd(ui: &mut egui::Ui, height: f32, view: &SpectrumView) {
+    let size = (view.psd.len().max(1) - 1) * 2;
+    Plot::new("Spectrum")
+        .height(height)
+        .x_axis_formatter(|x, _| format!("{x} Hz"))
+        .y_axis_formatter(|y, _| format!("{y} dB"))
+        .show(ui, |plot_ui| {
+            let points = view
+                .psd
+                .iter()
+                .enumerate()
+                .map(|(k, p)| {
+                    [
+                        bin_freq(k as f32, size, view.sample_rate) as f64,
+                        to_db(*p) as f64,
+                    ]
+                })
+                .collect::<Vec<_>>();
+            plot_ui.line(Line::new(points).color(Color32::LIGHT_BLUE));
+            for peak in view.peaks.iter() {
+                plot_ui.vline(VLine::new(peak.freq).color(Color32::GOLD));
+                plot_ui.text(
+                    Text::new(
+                        PlotPoint::new(peak.freq, to_db(peak.power)),
+                        peak_label(peak),
+                    )
+                    .color(Color32::GOLD)
+                    .anchor(egui::Align2::LEFT_BOTTOM),
+                );
+            }
+        });
+}
+
+pub fn show_spectrogram(ui: &mut egui::Ui, height: f32, view: &SpectrumView) {
+    Plot::new("Spectrogram")
+        .height(height)
+        .y_axis_formatter(|y, _| format!("{y} Hz"))
+        .show(ui, |plot_ui| {
+            let (Some(texture), Some(first), Some(last)) =
+                (&view.texture, view.columns.front(), view.columns.back())
+            else {
+                return;
+            };
+            let nyquist = view.sample_rate as f64 / 2.;
+            plot_ui.image(PlotImage::new(
+                texture.id(),
+                PlotPoint::new((first.0 + last.0) / 2., nyquist / 2.),
+                [(last.0 - first.0) as f32, nyquist as f32],
+            ));
+            for peak in view.peaks.iter() {
+                plot_ui.text(
+                    Text::new(PlotPoint::new(last.0, peak.freq), peak_label(peak))
+                        .color(Color32::WHITE)
+                        .anchor(egui::Align2::RIGHT_CENTER),
+                );
+            }
+        });
+}
+
+/// Channel, FFT size, window and peak count, for the "Plots" window.
+pub fn spectrum_settings_ui(ui: &mut egui::Ui, settings: &mut SpectrumSettings) {
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("spectrum_channel")
+            .selected_text(CHANNEL_NAME[settings.channel])
+            .show_ui(ui, |ui| {
+                for (i, name) in CHANNEL_NAME.iter().enumerate() {
+                    ui.selectable_value(&mut settings.channel, i, *name);
+                }
+            });
+        egui::ComboBox::from_id_source("spectrum_fft")
+            .selected_text(format!("FFT {}", settings.fft_size))
+            .show_ui(ui, |ui| {
+                for size in FFT_SIZES {
+                    ui.selectable_value(&mut settings.fft_size, size, size.to_string());
+                }
+            });
+        egui::ComboBox::from_id_source("spectrum_window")
+            .selected_text(format!("{:?}", settings.window))
+            .show_ui(ui, |ui| {
+                for window in Window::ALL {
+                    ui.selectable_value(&mut settings.window, window, format!("{window:?}"));
+                }
+            });
+    });
+    ui.horizontal(|ui| {
+        ui.add(egui::Slider::new(&mut settings.peaks, 0..=8).text("peaks"));
+        ui.checkbox(&mut settings.raw, "unfiltered");
+    });
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_egui::EguiContexts;
+
+use crate::gyro::{acc_to_model, gyro_update, GyroComponent, GyroSample};
+
+/// Length of the world and body axes.
+const AXIS_LENGTH: f32 = 3.;
+/// Length of an arrow for 1 g.
+const G_LENGTH: f32 = 3.;
+/// Weight of each new reading in the running average of the accelerometer
+/// magnitude, which is taken as 1 g.
+const ONE_G_SMOOTHING: f32 = 0.001;
+const GRID_COLOR: Color = Color::rgba(0.6, 0.6, 0.6, 0.4);
+const GRAVITY_COLOR: Color = Color::PURPLE;
+const ACC_COLOR: Color = Color::CYAN;
+const AXIS_COLOR: [Color; 3] = [Color::RED, Color::GREEN, Color::BLUE];
+
+/// Which reference geometry is drawn.
+#[derive(Resource)]
+pub struct ReferenceSettings {
+    pub grid: bool,
+    /// Lines either side of the centre, one unit apart.
+    pub grid_extent: u32,
+    pub world_axes: bool,
+    pub body_axes: bool,
+    pub gravity: bool,
+    pub acceleration: bool,
+}
+
+impl Default for ReferenceSettings {
+    fn default() -> Self {
+        Self {
+            grid: true,
+            grid_extent: 20,
+            world_axes: true,
+            body_axes: false,
+            gravity: false,
+            acceleration: false,
+        }
+    }
+}
+
+/// The newest sample of every source, for the acceleration arrows.
+#[derive(Resource, Default)]
+pub struct LatestSamples {
+    pub samples: HashMap<Entity, Vec<f32>>,
+    /// 1 g in the unit each source's accelerometer reports in, be it g,
+    /// m/s² or raw counts, from the average magnitude of its readings.
+    pub one_g: HashMap<Entity, f32>,
+}
+
+pub struct ReferencePlugin;
+
+impl Plugin for ReferencePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReferenceSettings>()
+            .init_resource::<LatestSamples>()
+            .add_systems(
+                Update,
+                (
+                    latest_samples.after(gyro_update),
+                    reference_draw.after(latest_samples),
+                    reference_ui,
+                ),
+            );
+    }
+}
+
+pub fn latest_samples(mut latest: ResMut<LatestSamples>, mut samples: EventReader<GyroSample>) {
+    for sample in samples.iter() {
+        let magnitude = acc_to_model(&sample.data).length();
+        latest
+            .one_g
+            .entry(sample.source)
+            .and_modify(|g| *g += ONE_G_SMOOTHING * (magnitude - *g))
+            .or_insert(magnitude);
+        latest.samples.insert(sample.source, sample.data.clone());
+    }
+}
+
+/// A line with a small head at `end`.
+fn arrow(gizmos: &mut Gizmos, start: Vec3, end: Vec3, color: Color) {
+    gizmos.line(start, end, color);
+    let along = end - start;
+    let length = along.length();
+    if length < 1e-3 {
+        return;
+    }
+    let dir = along / length;
+    let side = dir.any_orthonormal_vector();
+    let head = 0.15 * length.min(2.);
+    for s in [side, -side, dir.cross(side), -dir.cross(side)] {
+        gizmos.line(end, end - dir * head + s * head * 0.5, color);
+    }
+}
+
+pub fn reference_draw(
+    mut gizmos: Gizmos,
+    settings: Res<ReferenceSettings>,
+    latest: Res<LatestSamples>,
+    drones: Query<(&Transform, &GyroComponent)>,
+) {
+    if settings.grid {
+        // under the lowest drone, so it never cuts through a model
+        let floor = drones
+            .iter()
+            .map(|(t, _)| t.translation.y)
+            .fold(0., f32::min)
+            - 4.;
+        let n = settings.grid_extent as i32;
+        let extent = n as f32;
+        for i in -n..=n {
+            let i = i as f32;
+            gizmos.line(
+                Vec3::new(i, floor, -extent),
+                Vec3::new(i, floor, extent),
+                GRID_COLOR,
+            );
+            gizmos.line(
+                Vec3::new(-extent, floor, i),
+                Vec3::new(extent, floor, i),
+                GRID_COLOR,
+            );
+        }
+    }
+
+    if settings.world_axes {
+        for (axis, color) in [Vec3::X, Vec3::Y, Vec3::Z].into_iter().zip(AXIS_COLOR) {
+            arrow(&mut gizmos, Vec3::ZERO, axis * AXIS_LENGTH, color);
+        }
+    }
+
+    for (transform, gyro) in drones.iter() {
+        let origin = transform.translation;
+        if settings.body_axes {
+            for (axis, color) in [Vec3::X, Vec3::Y, Vec3::Z].into_iter().zip(AXIS_COLOR) {
+                let tip = origin + transform.rotation * axis * AXIS_LENGTH;
+                arrow(&mut gizmos, origin, tip, color);
+            }
+        }
+        if settings.gravity {
+            arrow(
+                &mut gizmos,
+                origin,
+                origin - Vec3::Y * G_LENGTH,
+                GRAVITY_COLOR,
+            );
+        }
+        if settings.acceleration {
+            let source = &gyro.source;
+            if let (Some(v), Some(one_g)) = (latest.samples.get(source), latest.one_g.get(source)) {
+                let scale = G_LENGTH / one_g.max(f32::EPSILON);
+                let measured = transform.rotation * acc_to_model(v) * scale;
+                arrow(&mut gizmos, origin, origin + measured, ACC_COLOR);
+            }
+        }
+    }
+}
+
+pub fn reference_ui(mut contexts: EguiContexts, mut settings: ResMut<ReferenceSettings>) {
+    egui::Window::new("Scene")
+        .default_pos([20., 240.])
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut settings.grid, "grid");
+                ui.add(egui::Slider::new(&mut settings.grid_extent, 5..=100).text("size"));
+            });
+            ui.checkbox(&mut settings.world_axes, "world axes");
+            ui.checkbox(&mut settings.body_axes, "body axes");
+            ui.checkbox(&mut settings.gravity, "gravity");
+            ui.checkbox(&mut settings.acceleration, "measured acceleration");
+            ui.label("At rest the measured acceleration points against gravity.");
+        });
+}
@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+
+/// Hard limit on stored points, whatever the window: at 1 kHz that is still
+/// a couple of minutes.
+const DEFAULT_CAPACITY: usize = 1 << 17;
+
+/// A time series that forgets everything older than a sliding window.
+///
+/// Points are `[t, value]` pairs with `t` in seconds of device time, so they
+/// can be handed to a plot as they are. Storage is a ring buffer: besides the
+/// window, the number of points is capped, so a long window on a fast device
+/// cannot grow without bound.
+#[derive(Clone)]
+pub struct Series {
+    points: VecDeque<[f64; 2]>,
+    capacity: usize,
+}
+
+impl Default for Series {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl Series {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            points: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, t: f64, value: f64) {
+        if self.points.len() == self.capacity {
+            self.points.pop_front();
+        }
+        self.points.push_back([t, value]);
+    }
+
+    /// Drops every point older than `window` seconds before the newest one.
+    pub fn trim(&mut self, window: f64) {
+        let Some(&[newest, _]) = self.points.back() else {
+            return;
+        };
+        while self.points.front().is_some_and(|p| p[0] < newest - window) {
+            self.points.pop_front();
+        }
+    }
+
+    pub fn points(&self) -> Vec<[f64; 2]> {
+        self.points.iter().copied().collect()
+    }
+
+    /// At most `max_points` points for drawing. Long windows are split into
+    /// buckets that each keep their minimum and maximum, so spikes survive.
+    pub fn decimated(&self, max_points: usize) -> Vec<[f64; 2]> {
+        if self.points.len() <= max_points || max_points < 2 {
+            return self.points();
+        }
+        let bucket = (self.points.len() * 2).div_ceil(max_points);
+        let (front, back) = self.points.as_slices();
+        let mut out = Vec::with_capacity(max_points + 2);
+        let mut chunk = Vec::with_capacity(bucket);
+        for p in front.iter().chain(back.iter()) {
+            chunk.push(*p);
+            if chunk.len() == bucket {
+                push_extremes(&mut out, &chunk);
+                chunk.clear();
+            }
+        }
+        push_extremes(&mut out, &chunk);
+        out
+    }
+
+    /// A copy of the points with `t0 <= t <= t1`.
+    pub fn slice(&self, t0: f64, t1: f64) -> Series {
+        let start = self.points.partition_point(|p| p[0] < t0);
+        let end = self.points.partition_point(|p| p[0] <= t1);
+        Series {
+            points: self.points.range(start..end).copied().collect(),
+            capacity: self.capacity,
+        }
+    }
+
+    /// Linearly interpolated value at `t`, if `t` lies within the series.
+    pub fn value_at(&self, t: f64) -> Option<f64> {
+        let i = self.points.partition_point(|p| p[0] < t);
+        let after = self.points.get(i)?;
+        if after[0] == t || i == 0 {
+            return (after[0] == t).then_some(after[1]);
+        }
+        let before = self.points[i - 1];
+        let f = (t - before[0]) / (after[0] - before[0]);
+        Some(before[1] + (after[1] - before[1]) * f)
+    }
+
+    /// How much time the series can hold at the rate seen so far, before
+    /// the point cap rather than the window decides what is dropped.
+    pub fn span_held(&self) -> Option<f64> {
+        let (first, last) = (self.points.front()?, self.points.back()?);
+        let spacing = (last[0] - first[0]) / (self.points.len() - 1) as f64;
+        (spacing > 0.).then_some(spacing * self.capacity as f64)
+    }
+
+    pub fn last(&self) -> Option<[f64; 2]> {
+        self.points.back().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+}
+
+fn push_extremes(out: &mut Vec<[f64; 2]>, chunk: &[[f64; 2]]) {
+    let Some(first) = chunk.first() else {
+        return;
+    };
+    let (mut lo, mut hi) = (first, first);
+    for p in chunk {
+        if p[1] < lo[1] {
+            lo = p;
+        }
+        if p[1] > hi[1] {
+            hi = p;
+        }
+    }
+    // keep them in time order so the line does not double back
+    if std::ptr::eq(lo, hi) {
+        out.push(*lo);
+    } else if lo[0] <= hi[0] {
+        out.extend([*lo, *hi]);
+    } else {
+        out.extend([*hi, *lo]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(len: usize, capacity: usize) -> Series {
+        let mut series = Series::with_capacity(capacity);
+        for i in 0..len {
+            series.push(i as f64 * 0.1, i as f64);
+        }
+        series
+    }
+
+    #[test]
+    fn drops_the_oldest_past_capacity() {
+        let series = ramp(10, 4);
+        assert_eq!(series.len(), 4);
+        assert_eq!(series.points()[0][1], 6.);
+        assert_eq!(series.last(), Some([0.9, 9.]));
+    }
+
+    #[test]
+    fn trims_to_the_window() {
+        let mut series = ramp(100, 1000);
+        series.trim(1.);
+        assert_eq!(series.points()[0][1], 89.);
+        assert_eq!(series.len(), 11);
+    }
+
+    #[test]
+    fn decimation_keeps_spikes_in_order() {
+        let mut series = ramp(1000, 10_000);
+        series.push(100.05, -50.);
+        series.push(100.1, 5000.);
+        for i in 0..1000 {
+            series.push(101. + i as f64 * 0.1, 0.);
+        }
+        let drawn = series.decimated(100);
+        assert!(drawn.len() <= 102, "{}", drawn.len());
+        assert!(drawn.contains(&[100.05, -50.]));
+        assert!(drawn.contains(&[100.1, 5000.]));
+        assert!(drawn.windows(2).all(|w| w[0][0] <= w[1][0]));
+        // short enough already
+        assert_eq!(ramp(10, 10).decimated(100).len(), 10);
+    }
+
+    #[test]
+    fn slices_inclusively() {
+        let slice = ramp(100, 1000).slice(1., 2.);
+        assert_eq!(slice.points().first().map(|p| p[1]), Some(10.));
+        assert_eq!(slice.points().last().map(|p| p[1]), Some(20.));
+        assert!(ramp(100, 1000).slice(20., 30.).is_empty());
+    }
+
+    #[test]
+    fn interpolates_inside_only() {
+        let series = ramp(10, 100);
+        assert_eq!(series.value_at(0.5), Some(5.));
+        assert!((series.value_at(0.25).unwrap() - 2.5).abs() < 1e-9);
+        assert_eq!(series.value_at(-0.1), None);
+        assert_eq!(series.value_at(1.), None);
+    }
+
+    #[test]
+    fn span_held_follows_the_rate() {
+        assert_eq!(Series::default().span_held(), None);
+        let held = ramp(10, 1000).span_held().unwrap();
+        assert!((held - 100.).abs() < 1e-9);
+    }
+}
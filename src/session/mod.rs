@@ -0,0 +1,373 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_egui::egui;
+use bevy_egui::EguiContexts;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::{camera_fit, camera_input, CameraMode, OrbitCamera};
+use crate::config::Config;
+use crate::gyro::{DroneLabel, GyroComponent, SelectedDrone, Source};
+use crate::plots::{PlotPanel, PlotSettings, SpectrumSettings};
+
+/// How often the session is written back, if anything changed.
+const AUTOSAVE_EVERY: Duration = Duration::from_secs(2);
+const DEFAULT_WORKSPACE: &str = "default";
+
+/// Estimator parameters of one drone, found again by its key.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DroneState {
+    pub acc_weight: f32,
+    pub calibration_samples: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CameraPose {
+    pub focus: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+    #[serde(default)]
+    pub mode: CameraMode,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlotState {
+    pub panels: Vec<PlotPanel>,
+    pub window: f64,
+    pub show_raw: bool,
+    pub spectrum: SpectrumSettings,
+}
+
+/// What a workspace brings back. Sources and drones are referred to by
+/// label, so a session outlives the entities it was taken from; drones
+/// sharing a label are told apart by `drone_keys`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub window: Option<[f32; 2]>,
+    /// egui's memory: where every panel is and whether it is open.
+    pub panels: Option<serde_json::Value>,
+    pub selected_drone: Option<String>,
+    pub plot_source: Option<String>,
+    pub drones: BTreeMap<String, DroneState>,
+    pub camera: Option<CameraPose>,
+    pub plots: Option<PlotState>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WorkspaceFile {
+    current: String,
+    workspaces: BTreeMap<String, Session>,
+}
+
+/// Named sessions, such as "bench calibration" or "flight review", and the
+/// one in use, which follows whatever is changed.
+#[derive(Resource)]
+pub struct Workspaces {
+    pub current: String,
+    pub saved: BTreeMap<String, Session>,
+    path: PathBuf,
+    restored: bool,
+    last_written: String,
+}
+
+impl Workspaces {
+    pub fn load(path: PathBuf) -> Self {
+        let file = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<WorkspaceFile>(&text).ok());
+        let (current, saved) = match file {
+            Some(file) => (file.current, file.workspaces),
+            None => (DEFAULT_WORKSPACE.to_owned(), BTreeMap::new()),
+        };
+        Self {
+            current,
+            saved,
+            path,
+            restored: false,
+            last_written: String::new(),
+        }
+    }
+
+    fn write(&mut self) -> Result<(), String> {
+        let file = WorkspaceFile {
+            current: self.current.clone(),
+            workspaces: self.saved.clone(),
+        };
+        let text = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        if text != self.last_written {
+            std::fs::write(&self.path, &text)
+                .map_err(|e| format!("{}: {e}", self.path.display()))?;
+            self.last_written = text;
+        }
+        Ok(())
+    }
+}
+
+impl FromWorld for Workspaces {
+    fn from_world(world: &mut World) -> Self {
+        let path = world
+            .get_resource::<Config>()
+            .map(|c| c.ui.session.clone())
+            .unwrap_or_else(|| Config::default().ui.session);
+        Self::load(path)
+    }
+}
+
+/// Everything a session is taken from and given back to.
+#[derive(SystemParam)]
+pub struct SessionState<'w, 's> {
+    contexts: EguiContexts<'w, 's>,
+    window: Query<'w, 's, &'static mut Window, With<PrimaryWindow>>,
+    camera: Query<'w, 's, &'static mut OrbitCamera>,
+    drones: Query<'w, 's, (Entity, &'static DroneLabel, &'static mut GyroComponent)>,
+    sources: Query<'w, 's, (Entity, &'static Source)>,
+    selected: ResMut<'w, SelectedDrone>,
+    plots: ResMut<'w, PlotSettings>,
+}
+
+impl SessionState<'_, '_> {
+    /// The key each drone is saved under: its label, followed by `#n` in
+    /// spawn order when several drones share it.
+    fn drone_keys(&self) -> HashMap<Entity, String> {
+        let mut drones = self
+            .drones
+            .iter()
+            .map(|(entity, label, _)| (entity, label.0.as_str()))
+            .collect::<Vec<_>>();
+        drones.sort_by_key(|(entity, _)| *entity);
+        let mut count = HashMap::<&str, usize>::new();
+        for (_, label) in &drones {
+            *count.entry(label).or_default() += 1;
+        }
+        let mut seen = HashMap::<&str, usize>::new();
+        drones
+            .into_iter()
+            .map(|(entity, label)| {
+                let n = seen.entry(label).or_default();
+                *n += 1;
+                let key = match count[label] {
+                    1 => label.to_owned(),
+                    _ => format!("{label}#{n}"),
+                };
+                (entity, key)
+            })
+            .collect()
+    }
+
+    pub fn capture(&mut self) -> Session {
+        let mut keys = self.drone_keys();
+        let selected = self.selected.0.and_then(|e| keys.get(&e).cloned());
+        let source = self
+            .plots
+            .source
+            .and_then(|e| self.sources.get(e).ok())
+            .map(|(_, s)| s.label.clone());
+        Session {
+            window: self
+                .window
+                .get_single()
+                .ok()
+                .map(|w| [w.resolution.width(), w.resolution.height()]),
+            panels: self
+                .contexts
+                .ctx_mut()
+                .memory(|m| serde_json::to_value(m).ok()),
+            selected_drone: selected,
+            plot_source: source,
+            drones: self
+                .drones
+                .iter()
+                .filter_map(|(entity, _, gyro)| {
+                    let state = DroneState {
+                        acc_weight: gyro.acc_weight,
+                        calibration_samples: gyro.calibration_samples,
+                    };
+                    Some((keys.remove(&entity)?, state))
+                })
+                .collect(),
+            camera: self.camera.get_single().ok().map(|c| CameraPose {
+                focus: c.focus.into(),
+                yaw: c.yaw,
+                pitch: c.pitch,
+                distance: c.distance,
+                mode: c.mode,
+            }),
+            plots: Some(PlotState {
+                panels: self.plots.panels.clone(),
+                window: self.plots.window,
+                show_raw: self.plots.show_raw,
+                spectrum: self.plots.spectrum.clone(),
+            }),
+        }
+    }
+
+    pub fn apply(&mut self, session: &Session) {
+        if let (Some([width, height]), Ok(mut window)) =
+            (session.window, self.window.get_single_mut())
+        {
+            window.resolution.set(width, height);
+        }
+        if let Some(memory) = session
+            .panels
+            .clone()
+            .and_then(|v| serde_json::from_value::<egui::Memory>(v).ok())
+        {
+            self.contexts.ctx_mut().memory_mut(|m| *m = memory);
+        }
+        let keys = self.drone_keys();
+        if let Some(name) = &session.selected_drone {
+            let found = keys.iter().find(|(_, key)| *key == name);
+            if let Some((entity, _)) = found {
+                self.selected.0 = Some(*entity);
+            }
+        }
+        if let Some(name) = &session.plot_source {
+            let found = self.sources.iter().find(|(_, s)| &s.label == name);
+            if let Some((entity, _)) = found {
+                self.plots.source = Some(entity);
+            }
+        }
+        for (entity, _, mut gyro) in self.drones.iter_mut() {
+            if let Some(state) = keys.get(&entity).and_then(|k| session.drones.get(k)) {
+                gyro.acc_weight = state.acc_weight;
+                gyro.calibration_samples = state.calibration_samples;
+            }
+        }
+        if let (Some(pose), Ok(mut camera)) = (&session.camera, self.camera.get_single_mut()) {
+            camera.focus = pose.focus.into();
+            camera.yaw = pose.yaw;
+            camera.pitch = pose.pitch;
+            camera.distance = pose.distance;
+            camera.mode = pose.mode;
+        }
+        if let Some(plots) = &session.plots {
+            // panels added since keep their defaults
+            for panel in self.plots.panels.iter_mut() {
+                if let Some(saved) = plots.panels.iter().find(|p| p.kind == panel.kind) {
+                    *panel = saved.clone();
+                }
+            }
+            self.plots.window = plots.window;
+            self.plots.show_raw = plots.show_raw;
+            self.plots.spectrum = plots.spectrum.clone();
+        }
+    }
+}
+
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Workspaces>().add_systems(
+            Update,
+            (
+                // the camera fits itself around the drones on the first
+                // frame, the saved pose wins over that
+                session_restore.after(camera_fit),
+                session_autosave.after(session_restore),
+                workspace_menu.before(camera_input),
+            ),
+        );
+    }
+}
+
+pub fn session_restore(mut workspaces: ResMut<Workspaces>, mut state: SessionState) {
+    if workspaces.restored {
+        return;
+    }
+    workspaces.restored = true;
+    if let Some(session) = workspaces.saved.get(&workspaces.current).cloned() {
+        state.apply(&session);
+    }
+}
+
+pub fn session_autosave(
+    time: Res<Time>,
+    mut since: Local<Duration>,
+    mut workspaces: ResMut<Workspaces>,
+    mut state: SessionState,
+) {
+    *since += time.delta();
+    if !workspaces.restored || *since < AUTOSAVE_EVERY {
+        return;
+    }
+    *since = Duration::ZERO;
+    let current = workspaces.current.clone();
+    workspaces.saved.insert(current, state.capture());
+    if let Err(e) = workspaces.write() {
+        warn!("session: {e}");
+    }
+}
+
+pub fn workspace_menu(
+    mut workspaces: ResMut<Workspaces>,
+    mut name: Local<String>,
+    mut state: SessionState,
+) {
+    let mut switch = None;
+    let mut delete = false;
+    let ctx = state.contexts.ctx_mut().clone();
+    egui::TopBottomPanel::top("workspace_menu").show(&ctx, |ui| {
+        egui::menu::bar(ui, |ui| {
+            ui.menu_button(format!("Workspace: {}", workspaces.current), |ui| {
+                for saved in workspaces.saved.keys() {
+                    let current = *saved == workspaces.current;
+                    if ui.selectable_label(current, saved).clicked() && !current {
+                        switch = Some(saved.clone());
+                        ui.close_menu();
+                    }
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut *name);
+                    let trimmed = name.trim();
+                    let fresh = !trimmed.is_empty() && !workspaces.saved.contains_key(trimmed);
+                    if ui
+                        .add_enabled(fresh, egui::Button::new("Save as"))
+                        .clicked()
+                    {
+                        switch = Some(name.trim().to_owned());
+                        name.clear();
+                        ui.close_menu();
+                    }
+                });
+                if ui
+                    .add_enabled(
+                        workspaces.saved.len() > 1,
+                        egui::Button::new("Delete this workspace"),
+                    )
+                    .clicked()
+                {
+                    delete = true;
+                    ui.close_menu();
+                }
+            });
+        });
+    });
+
+    if delete {
+        let current = workspaces.current.clone();
+        workspaces.saved.remove(&current);
+        switch = workspaces.saved.keys().next().cloned();
+    } else if switch.is_some() {
+        // a new name starts as a copy of what is on screen
+        let current = workspaces.current.clone();
+        let session = state.capture();
+        workspaces.saved.insert(current, session.clone());
+        if let Some(new) = &switch {
+            workspaces.saved.entry(new.clone()).or_insert(session);
+        }
+    }
+    if let Some(new) = switch {
+        if let Some(session) = workspaces.saved.get(&new).cloned() {
+            state.apply(&session);
+        }
+        workspaces.current = new;
+    }
+}
@@ -0,0 +1,236 @@
+use std::sync::Arc;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+
+/// Taper applied to every FFT segment.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl Window {
+    pub const ALL: [Window; 4] = [
+        Window::Rectangular,
+        Window::Hann,
+        Window::Hamming,
+        Window::Blackman,
+    ];
+
+    pub fn coefficients(&self, size: usize) -> Vec<f32> {
+        let n = (size.max(2) - 1) as f32;
+        let tau = std::f32::consts::TAU;
+        (0..size)
+            .map(|i| {
+                let x = i as f32 / n;
+                match self {
+                    Window::Rectangular => 1.,
+                    Window::Hann => 0.5 - 0.5 * (tau * x).cos(),
+                    Window::Hamming => 0.54 - 0.46 * (tau * x).cos(),
+                    Window::Blackman => 0.42 - 0.5 * (tau * x).cos() + 0.08 * (2. * tau * x).cos(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A local maximum of a spectrum.
+#[derive(Clone, Copy, Debug)]
+pub struct Peak {
+    /// Hz, refined between bins.
+    pub freq: f32,
+    /// Power spectral density at the peak, in units² / Hz.
+    pub power: f32,
+}
+
+/// One FFT size and window, planned once and reused for every segment.
+pub struct Spectrum {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// Sum of the squared window, for PSD scaling.
+    window_power: f32,
+}
+
+impl Spectrum {
+    pub fn new(size: usize, window: Window) -> Self {
+        let window = window.coefficients(size);
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(size),
+            window_power: window.iter().map(|w| w * w).sum(),
+            window,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Number of bins in a one-sided spectrum, DC and Nyquist included.
+    pub fn bins(&self) -> usize {
+        self.size() / 2 + 1
+    }
+
+    /// One-sided periodogram of the first `size` samples, mean removed.
+    pub fn periodogram(&self, samples: &[f32], sample_rate: f32) -> Vec<f32> {
+        let size = self.size();
+        let mean = samples[..size].iter().sum::<f32>() / size as f32;
+        let mut buffer = samples[..size]
+            .iter()
+            .zip(&self.window)
+            .map(|(x, w)| Complex::new((x - mean) * w, 0.))
+            .collect::<Vec<_>>();
+        self.fft.process(&mut buffer);
+
+        let scale = 1. / (sample_rate * self.window_power);
+        let bins = self.bins();
+        buffer[..bins]
+            .iter()
+            .enumerate()
+            .map(|(k, c)| {
+                // everything above Nyquist folds onto its mirror bin
+                let one_sided = if k == 0 || 2 * k == size { 1. } else { 2. };
+                c.norm_sqr() * scale * one_sided
+            })
+            .collect()
+    }
+
+    /// Welch power spectral density: the average periodogram of segments
+    /// overlapping by half. `None` if there is less than one segment.
+    pub fn welch(&self, samples: &[f32], sample_rate: f32) -> Option<Vec<f32>> {
+        let size = self.size();
+        if samples.len() < size {
+            return None;
+        }
+        let hop = (size / 2).max(1);
+        let mut psd = vec![0.; self.bins()];
+        let mut segments = 0;
+        for start in (0..=samples.len() - size).step_by(hop) {
+            for (acc, p) in psd
+                .iter_mut()
+                .zip(self.periodogram(&samples[start..], sample_rate))
+            {
+                *acc += p;
+            }
+            segments += 1;
+        }
+        psd.iter_mut().for_each(|p| *p /= segments as f32);
+        Some(psd)
+    }
+}
+
+/// Frequency of bin `k` for an FFT of `size` samples.
+pub fn bin_freq(k: f32, size: usize, sample_rate: f32) -> f32 {
+    k * sample_rate / size as f32
+}
+
+/// The `count` strongest local maxima above `min_freq`, strongest first.
+/// Each is refined by fitting a parabola through its bin and both
+/// neighbours in dB.
+pub fn dominant_peaks(
+    psd: &[f32],
+    size: usize,
+    sample_rate: f32,
+    min_freq: f32,
+    count: usize,
+) -> Vec<Peak> {
+    let db = psd.iter().map(|p| to_db(*p)).collect::<Vec<_>>();
+    let mut peaks = (1..db.len().saturating_sub(1))
+        .filter(|&k| bin_freq(k as f32, size, sample_rate) >= min_freq)
+        .filter(|&k| db[k] > db[k - 1] && db[k] >= db[k + 1])
+        .map(|k| {
+            let (a, b, c) = (db[k - 1], db[k], db[k + 1]);
+            let denom = a - 2. * b + c;
+            let offset = if denom != 0. {
+                0.5 * (a - c) / denom
+            } else {
+                0.
+            };
+            Peak {
+                freq: bin_freq(k as f32 + offset, size, sample_rate),
+                power: psd[k],
+            }
+        })
+        .collect::<Vec<_>>();
+    peaks.sort_by(|a, b| b.power.total_cmp(&a.power));
+    peaks.truncate(count);
+    peaks
+}
+
+/// Average sample rate of `[t, value]` points spanning device time.
+pub fn sample_rate(points: &[[f64; 2]]) -> Option<f32> {
+    let (first, last) = (points.first()?, points.last()?);
+    let span = last[0] - first[0];
+    (span > 0.).then(|| ((points.len() - 1) as f64 / span) as f32)
+}
+
+pub fn to_db(power: f32) -> f32 {
+    10. * power.max(1e-20).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f32 = 1000.;
+    const SIZE: usize = 256;
+
+    fn tones(tones: &[(f32, f32)], len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / FS;
+                tones
+                    .iter()
+                    .map(|(freq, amp)| amp * (std::f32::consts::TAU * freq * t).sin())
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_a_sine_between_bins() {
+        let spectrum = Spectrum::new(SIZE, Window::Hann);
+        let psd = spectrum.welch(&tones(&[(123.4, 1.)], 4096), FS).unwrap();
+        assert_eq!(psd.len(), SIZE / 2 + 1);
+        let peaks = dominant_peaks(&psd, SIZE, FS, 0., 1);
+        // a bin is almost 4 Hz wide
+        assert!((peaks[0].freq - 123.4).abs() < 0.5, "{:?}", peaks[0]);
+    }
+
+    #[test]
+    fn psd_integrates_to_the_signal_power() {
+        for window in Window::ALL {
+            let spectrum = Spectrum::new(SIZE, window);
+            let psd = spectrum.welch(&tones(&[(200., 2.)], 4096), FS).unwrap();
+            let power = psd.iter().sum::<f32>() * FS / SIZE as f32;
+            // a sine of amplitude 2 has a mean square of 2
+            assert!((power - 2.).abs() < 0.1, "{window:?}: {power}");
+        }
+    }
+
+    #[test]
+    fn peaks_come_strongest_first_above_the_floor() {
+        let spectrum = Spectrum::new(SIZE, Window::Hann);
+        let samples = tones(&[(20., 3.), (150., 1.), (300., 2.)], 4096);
+        let psd = spectrum.welch(&samples, FS).unwrap();
+        let peaks = dominant_peaks(&psd, SIZE, FS, 50., 2);
+        let freqs = peaks.iter().map(|p| p.freq.round()).collect::<Vec<_>>();
+        assert_eq!(freqs, [300., 150.]);
+    }
+
+    #[test]
+    fn needs_a_whole_segment() {
+        let spectrum = Spectrum::new(SIZE, Window::Hann);
+        assert!(spectrum.welch(&[0.; SIZE - 1], FS).is_none());
+    }
+
+    #[test]
+    fn sample_rate_from_device_time() {
+        let points = (0..11).map(|i| [i as f64 * 0.002, 0.]).collect::<Vec<_>>();
+        assert!((sample_rate(&points).unwrap() - 500.).abs() < 1e-3);
+        assert_eq!(sample_rate(&points[..1]), None);
+    }
+}
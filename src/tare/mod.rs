@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_egui::EguiContexts;
+
+use crate::gyro::{gyro_update, DroneVariant, GyroComponent, SelectedDrone};
+
+/// Ways of re-zeroing a drone without restarting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TareAction {
+    /// The current attitude becomes level, the heading stays.
+    Level,
+    /// The current heading becomes zero, the tilt stays.
+    Heading,
+    /// Back to the attitude as estimated.
+    Clear,
+    /// The gyro-only estimate jumps back to the accelerometer's tilt.
+    Realign,
+}
+
+impl TareAction {
+    pub const ALL: [TareAction; 4] = [Self::Level, Self::Heading, Self::Clear, Self::Realign];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Level => "Tare level",
+            Self::Heading => "Zero heading",
+            Self::Clear => "Clear tare",
+            Self::Realign => "Realign to acc",
+        }
+    }
+
+    pub fn key(self) -> KeyCode {
+        match self {
+            Self::Level => KeyCode::T,
+            Self::Heading => KeyCode::H,
+            Self::Clear => KeyCode::C,
+            Self::Realign => KeyCode::G,
+        }
+    }
+
+    /// Whether it does anything for this estimator.
+    pub fn applies_to(self, variant: DroneVariant) -> bool {
+        match self {
+            Self::Realign => matches!(variant, DroneVariant::Gyro | DroneVariant::Both),
+            _ => true,
+        }
+    }
+
+    pub fn apply(self, gyro: &mut GyroComponent, transform: &mut Transform) {
+        match self {
+            Self::Level => gyro.tare_level(),
+            Self::Heading => gyro.tare_heading(),
+            Self::Clear => gyro.tare = Quat::IDENTITY,
+            // takes effect with the next sample
+            Self::Realign => gyro.realign(),
+        }
+        transform.rotation = gyro.displayed();
+    }
+}
+
+pub struct TarePlugin;
+
+impl Plugin for TarePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (tare_input.after(gyro_update), tare_ui));
+    }
+}
+
+/// The selected drone, or every drone with shift held.
+pub fn tare_input(
+    mut contexts: EguiContexts,
+    keys: Res<Input<KeyCode>>,
+    selected: Res<SelectedDrone>,
+    mut drones: Query<(Entity, &mut GyroComponent, &mut Transform)>,
+) {
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    let all = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let shown = selected.get(drones.iter().map(|(e, ..)| e));
+    for action in TareAction::ALL {
+        if !keys.just_pressed(action.key()) {
+            continue;
+        }
+        for (entity, mut gyro, mut transform) in drones.iter_mut() {
+            if all || shown == Some(entity) {
+                action.apply(&mut gyro, &mut transform);
+            }
+        }
+    }
+}
+
+pub fn tare_ui(
+    mut contexts: EguiContexts,
+    mut drones: Query<(&mut GyroComponent, &mut Transform)>,
+) {
+    egui::Window::new("Tare")
+        .default_pos([20., 400.])
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label("every drone:");
+            ui.horizontal(|ui| {
+                for action in TareAction::ALL {
+                    if ui.button(action.name()).clicked() {
+                        for (mut gyro, mut transform) in drones.iter_mut() {
+                            action.apply(&mut gyro, &mut transform);
+                        }
+                    }
+                }
+            });
+            ui.separator();
+            for action in TareAction::ALL {
+                ui.label(format!("{:?}: {}", action.key(), action.name()));
+            }
+            ui.label("keys act on the selected drone, with shift on every drone");
+            ui.label("per-drone buttons are in the inspector");
+        });
+}
@@ -0,0 +1,339 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_egui::EguiContexts;
+
+use crate::gyro::{gyro_update, DroneColor, GyroComponent, HomePosition};
+use crate::model::{ModelInstance, SceneTint, Tinted};
+
+/// Distance of the traced tip from the drone's origin.
+const TIP_LENGTH: f32 = 3.;
+const AXIS_NAMES: [&str; 3] = ["X", "Y", "Z"];
+
+#[derive(Resource, PartialEq, Clone)]
+pub struct TrailSettings {
+    pub trail: bool,
+    /// Body axis whose tip is traced, 0..3 for X, Y, Z.
+    pub axis: usize,
+    pub trail_seconds: f32,
+    pub ghosts: bool,
+    pub ghost_count: usize,
+    /// Seconds between two ghosts.
+    pub ghost_interval: f32,
+    /// Draws every drone of a source on top of the first one.
+    pub overlay: bool,
+    pub overlay_alpha: f32,
+}
+
+impl Default for TrailSettings {
+    fn default() -> Self {
+        Self {
+            trail: false,
+            axis: 2,
+            trail_seconds: 3.,
+            ghosts: false,
+            ghost_count: 4,
+            ghost_interval: 0.25,
+            overlay: false,
+            overlay_alpha: 0.4,
+        }
+    }
+}
+
+impl TrailSettings {
+    fn span(&self) -> f32 {
+        self.trail_seconds
+            .max(self.ghost_count as f32 * self.ghost_interval)
+    }
+}
+
+/// Recent attitudes of a drone, oldest first, stamped with the app time.
+#[derive(Component, Default)]
+pub struct AttitudeHistory(pub VecDeque<(f32, Quat)>);
+
+impl AttitudeHistory {
+    /// The attitude the drone had at `time`, if the history goes back that
+    /// far.
+    pub fn at(&self, time: f32) -> Option<Quat> {
+        let i = self.0.partition_point(|(t, _)| *t <= time);
+        (i > 0).then(|| self.0[i - 1].1)
+    }
+}
+
+/// A see-through copy of a drone's model showing where it was `age + 1`
+/// intervals ago.
+#[derive(Component)]
+pub struct Ghost {
+    drone: Entity,
+    age: usize,
+    look: Look,
+}
+
+/// What a model is drawn from: the mesh of an OBJ or the scene of a glTF.
+#[derive(Clone, PartialEq)]
+enum Look {
+    Mesh(Handle<Mesh>),
+    Scene(Handle<Scene>),
+}
+
+pub struct TrailsPlugin;
+
+impl Plugin for TrailsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TrailSettings>().add_systems(
+            Update,
+            (
+                (history_record, overlay_apply).after(gyro_update),
+                trails_draw.after(history_record),
+                ghosts_sync.after(history_record),
+                trails_ui,
+            ),
+        );
+    }
+}
+
+pub fn history_record(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<TrailSettings>,
+    new: Query<Entity, (With<GyroComponent>, Without<AttitudeHistory>)>,
+    mut drones: Query<(&Transform, &mut AttitudeHistory)>,
+) {
+    for drone in new.iter() {
+        commands.entity(drone).insert(AttitudeHistory::default());
+    }
+    let now = time.elapsed_seconds();
+    // one sample is kept past the span so the oldest ghost always finds one
+    let oldest = now - settings.span() - 1.;
+    for (transform, mut history) in drones.iter_mut() {
+        history.0.push_back((now, transform.rotation));
+        while history.0.len() > 2 && history.0[1].0 < oldest {
+            history.0.pop_front();
+        }
+    }
+}
+
+/// Moves the drones onto their source's first drone in overlay mode, back
+/// home otherwise, and makes their models see-through accordingly.
+#[allow(clippy::type_complexity)]
+pub fn overlay_apply(
+    settings: Res<TrailSettings>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut drones: Query<(Entity, &HomePosition, &GyroComponent, &mut Transform)>,
+    models: Query<(Entity, &Parent), With<ModelInstance>>,
+    children: Query<&Children>,
+    // glTF meshes only once `scene_tint` gave them a material of their own
+    parts: Query<&Handle<StandardMaterial>, Or<(With<ModelInstance>, With<Tinted>)>>,
+) {
+    let mut anchors = HashMap::new();
+    for (_, home, gyro, _) in drones.iter() {
+        anchors.entry(gyro.source).or_insert(home.0);
+    }
+    for (_, home, gyro, mut transform) in drones.iter_mut() {
+        let target = if settings.overlay {
+            anchors[&gyro.source]
+        } else {
+            home.0
+        };
+        if transform.translation != target {
+            transform.translation = target;
+        }
+    }
+
+    let (alpha, mode) = if settings.overlay {
+        (settings.overlay_alpha, AlphaMode::Blend)
+    } else {
+        (1., AlphaMode::Opaque)
+    };
+    for (model, parent) in models.iter() {
+        if drones.get(parent.get()).is_err() {
+            continue;
+        }
+        // an OBJ model is a single mesh, a glTF one a scene of them
+        let meshes = std::iter::once(model).chain(children.iter_descendants(model));
+        for handle in meshes.filter_map(|e| parts.get(e).ok()) {
+            let current = materials.get(handle);
+            if current.is_some_and(|m| m.alpha_mode == mode && m.base_color.a() == alpha) {
+                continue;
+            }
+            if let Some(material) = materials.get_mut(handle) {
+                material.base_color.set_a(alpha);
+                material.alpha_mode = mode;
+            }
+        }
+    }
+}
+
+pub fn trails_draw(
+    mut gizmos: Gizmos,
+    time: Res<Time>,
+    settings: Res<TrailSettings>,
+    drones: Query<(&Transform, &DroneColor, &AttitudeHistory)>,
+) {
+    if !settings.trail {
+        return;
+    }
+    let now = time.elapsed_seconds();
+    let axis = [Vec3::X, Vec3::Y, Vec3::Z][settings.axis] * TIP_LENGTH;
+    for (transform, color, history) in drones.iter() {
+        let points = history
+            .0
+            .iter()
+            .filter(|(t, _)| now - t <= settings.trail_seconds)
+            .map(|(t, rotation)| {
+                let fade = 1. - (now - t) / settings.trail_seconds;
+                let tip = transform.translation + *rotation * axis;
+                (tip, color.0.with_a(fade.clamp(0., 1.)))
+            });
+        gizmos.linestrip_gradient(points);
+    }
+}
+
+/// Keeps `ghost_count` ghosts per drone, positioned from the history. A
+/// glTF ghost is a copy of the scene, tinted see-through by `scene_tint`.
+#[allow(clippy::type_complexity)]
+pub fn ghosts_sync(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<TrailSettings>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    drones: Query<(&Transform, &DroneColor, &AttitudeHistory)>,
+    models: Query<
+        (
+            &Parent,
+            &Transform,
+            Option<&Handle<Mesh>>,
+            Option<&Handle<Scene>>,
+        ),
+        With<ModelInstance>,
+    >,
+    mut ghosts: Query<
+        (Entity, &Ghost, &mut Transform, &mut Visibility),
+        (Without<AttitudeHistory>, Without<ModelInstance>),
+    >,
+) {
+    // ghosts are rebuilt whenever the settings move, their fade depends on
+    // the count
+    if !settings.ghosts || settings.is_changed() {
+        for (entity, ..) in ghosts.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        if !settings.ghosts {
+            return;
+        }
+    }
+
+    let looks: HashMap<Entity, (&Transform, Look)> = models
+        .iter()
+        .filter_map(|(parent, offset, mesh, scene)| {
+            let look = match (mesh, scene) {
+                (Some(mesh), _) => Look::Mesh(mesh.clone()),
+                (None, Some(scene)) => Look::Scene(scene.clone()),
+                (None, None) => return None,
+            };
+            Some((parent.get(), (offset, look)))
+        })
+        .collect();
+    let now = time.elapsed_seconds();
+    let mut present = HashSet::new();
+    if !settings.is_changed() {
+        for (entity, ghost, mut transform, mut visibility) in ghosts.iter_mut() {
+            let model = looks.get(&ghost.drone).filter(|(_, l)| *l == ghost.look);
+            let (Some((offset, _)), Ok((drone, _, history))) = (model, drones.get(ghost.drone))
+            else {
+                commands.entity(entity).despawn_recursive();
+                continue;
+            };
+            present.insert((ghost.drone, ghost.age));
+            let time = now - (ghost.age + 1) as f32 * settings.ghost_interval;
+            match history.at(time) {
+                Some(rotation) => {
+                    *transform = Transform::from_translation(drone.translation)
+                        .with_rotation(rotation)
+                        .mul_transform(**offset);
+                    *visibility = Visibility::Inherited;
+                }
+                None => *visibility = Visibility::Hidden,
+            }
+        }
+    }
+
+    for (drone, (offset, look)) in looks.iter() {
+        let Ok((_, color, _)) = drones.get(*drone) else {
+            continue;
+        };
+        for age in 0..settings.ghost_count {
+            if present.contains(&(*drone, age)) {
+                continue;
+            }
+            let fade = 0.5 * (1. - age as f32 / settings.ghost_count as f32);
+            let ghost = Ghost {
+                drone: *drone,
+                age,
+                look: look.clone(),
+            };
+            match look {
+                Look::Mesh(mesh) => commands.spawn((
+                    PbrBundle {
+                        mesh: mesh.clone(),
+                        material: materials.add(StandardMaterial {
+                            base_color: color.0.with_a(fade),
+                            alpha_mode: AlphaMode::Blend,
+                            ..default()
+                        }),
+                        transform: **offset,
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    NotShadowCaster,
+                    ghost,
+                )),
+                Look::Scene(scene) => commands.spawn((
+                    SceneBundle {
+                        scene: scene.clone(),
+                        transform: **offset,
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    SceneTint(color.0.with_a(fade)),
+                    ghost,
+                )),
+            };
+        }
+    }
+}
+
+pub fn trails_ui(mut contexts: EguiContexts, mut settings: ResMut<TrailSettings>) {
+    // edited on a copy so the ghosts are only rebuilt on a real change
+    let mut edit = settings.clone();
+    egui::Window::new("Trails")
+        .default_pos([20., 360.])
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut edit.trail, "trail of");
+                egui::ComboBox::from_id_source("trail_axis")
+                    .width(40.)
+                    .selected_text(AXIS_NAMES[edit.axis])
+                    .show_ui(ui, |ui| {
+                        for (i, name) in AXIS_NAMES.iter().enumerate() {
+                            ui.selectable_value(&mut edit.axis, i, *name);
+                        }
+                    });
+                ui.label("axis tip");
+            });
+            ui.add(egui::Slider::new(&mut edit.trail_seconds, 0.5..=30.0).text("seconds"));
+            ui.separator();
+            ui.checkbox(&mut edit.ghosts, "ghosts");
+            ui.add(egui::Slider::new(&mut edit.ghost_count, 1..=10).text("count"));
+            ui.add(egui::Slider::new(&mut edit.ghost_interval, 0.05..=2.0).text("every, s"));
+            ui.separator();
+            ui.checkbox(&mut edit.overlay, "overlay the estimators of a source");
+            ui.add(egui::Slider::new(&mut edit.overlay_alpha, 0.1..=1.0).text("opacity"));
+        });
+    if edit != *settings {
+        *settings = edit;
+    }
+}